@@ -16,6 +16,99 @@ pub struct Config {
 
     /// If set to true, the program will ignore the Synology index files which are created on Synology NAS devices.
     pub synology: bool,
+
+    /// The way in which images are brought into the target directory: moved, copied, hardlinked,
+    /// symlinked or reflinked (cloned where the filesystem supports it, copied otherwise).
+    #[serde(default)]
+    pub import_mode: ImportMode,
+
+    /// The number of worker threads used to read metadata and hash files, where 0 uses one per CPU core.
+    #[serde(default)]
+    pub threads: usize,
+
+    /// The number of seconds which must have passed since a file was last modified before it is
+    /// imported, so that files which are still being copied into the source directory are left alone.
+    #[serde(default)]
+    pub settle_time: u64,
+
+    /// If set to true, JPEG and TIFF-based files are only imported once they are structurally
+    /// complete, which catches files that are still being written without changing their timestamp.
+    #[serde(default)]
+    pub verify_complete: bool,
+
+    /// The sidecar files which are moved alongside the images they describe.
+    #[serde(default)]
+    pub sidecars: SidecarConfig,
+
+    /// The way in which RAW+JPEG pairs are imported.
+    #[serde(default)]
+    pub pairs: PairConfig,
+
+    /// The way in which Live Photos and Motion Photos are imported.
+    #[serde(default)]
+    pub motion_photos: MotionPhotoConfig,
+
+    /// The way in which Google Takeout JSON sidecars are handled once their photo has been imported.
+    #[serde(default)]
+    pub takeout: TakeoutConfig,
+
+    /// The persistent index of content hashes used to detect duplicates anywhere in the target library.
+    #[serde(default)]
+    pub index: IndexConfig,
+
+    /// The persistent ledger of every file ever imported, used to avoid re-importing deleted photos.
+    #[serde(default)]
+    pub ledger: LedgerConfig,
+
+    /// The persistent cache used to avoid rehashing files which have not changed.
+    #[serde(default)]
+    pub hash_cache: HashCacheConfig,
+
+    /// The way in which images which collide with an existing file in the target library are handled.
+    #[serde(default)]
+    pub conflict: ConflictConfig,
+
+    /// Where duplicate images are moved instead of being deleted, and how long they are kept there.
+    #[serde(default)]
+    pub quarantine: QuarantineConfig,
+
+    /// The journal of every change made during a run, which allows it to be undone.
+    #[serde(default)]
+    pub journal: JournalConfig,
+
+    /// The way in which files are removed from the source or target directories.
+    #[serde(default)]
+    pub removal: RemovalConfig,
+
+    /// The way in which `imgsort dedupe` handles duplicate files within a library.
+    #[serde(default)]
+    pub dedupe: DedupeConfig,
+
+    /// The detection of near-duplicate images (re-saved, resized or stripped copies) using perceptual hashes.
+    #[serde(default)]
+    pub similar: SimilarConfig,
+
+    /// The way in which `imgsort watch` waits for new files to finish being written.
+    #[serde(default)]
+    pub watch: WatchConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SidecarConfig {
+    /// The extensions (without a leading '.') of files which are treated as sidecars of an image sharing their name.
+    pub extensions: Vec<String>,
+}
+
+impl Default for SidecarConfig {
+    fn default() -> Self {
+        SidecarConfig {
+            extensions: ["xmp", "pp3", "dop", "aae", "thm"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
 }
 
 impl Default for Config {
@@ -25,20 +118,383 @@ impl Default for Config {
             target: PathBuf::from("photos"),
             template: "{year}/{date_time}-{name}".to_string(),
             synology: false,
+            import_mode: ImportMode::default(),
+            threads: 0,
+            settle_time: 0,
+            verify_complete: false,
+            sidecars: SidecarConfig::default(),
+            pairs: PairConfig::default(),
+            motion_photos: MotionPhotoConfig::default(),
+            takeout: TakeoutConfig::default(),
+            index: IndexConfig::default(),
+            ledger: LedgerConfig::default(),
+            hash_cache: HashCacheConfig::default(),
+            conflict: ConflictConfig::default(),
+            quarantine: QuarantineConfig::default(),
+            journal: JournalConfig::default(),
+            removal: RemovalConfig::default(),
+            dedupe: DedupeConfig::default(),
+            similar: SimilarConfig::default(),
+            watch: WatchConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    #[default]
+    Move,
+    Copy,
+    Hardlink,
+    Symlink,
+    Reflink,
+}
+
+impl ImportMode {
+    /// The shell command equivalent to importing a file in this mode, used when logging.
+    pub fn command(&self) -> &'static str {
+        match self {
+            ImportMode::Move => "mv",
+            ImportMode::Copy => "cp",
+            ImportMode::Hardlink => "ln",
+            ImportMode::Symlink => "ln -s",
+            ImportMode::Reflink => "cp --reflink",
         }
     }
 }
 
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PairConfig {
+    /// If set to true, the JPEG half of a RAW+JPEG pair will be removed instead of being imported.
+    pub discard_jpeg: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct MotionPhotoConfig {
+    /// If set to true, the video embedded in a Motion Photo will be extracted into its own file next to the still image.
+    pub extract_video: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct TakeoutConfig {
+    /// Whether the JSON sidecar is moved alongside its photo or deleted once the photo has been imported.
+    pub sidecar: TakeoutSidecarAction,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TakeoutSidecarAction {
+    #[default]
+    Move,
+    Delete,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct IndexConfig {
+    /// If set to true, images which are identical to any file already in the target library are treated as duplicates.
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct LedgerConfig {
+    /// If set to true, the content hash of every imported file is recorded in a ledger within the target library.
+    pub enabled: bool,
+
+    /// What happens to images which were imported before: they are left in the source directory
+    /// ("skip"), removed from it ("delete") or imported again ("reimport").
+    pub previously_imported: PreviouslyImported,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviouslyImported {
+    #[default]
+    Skip,
+    Delete,
+    Reimport,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HashCacheConfig {
+    /// Where cached hashes are stored: not at all ("none"), in a database within the target
+    /// library ("database") or in the `user.imgsort.sha256` extended attribute of each file ("xattr").
+    pub backend: HashCacheBackend,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashCacheBackend {
+    #[default]
+    None,
+    Database,
+    Xattr,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ConflictConfig {
+    /// The strategy used when an image collides with an existing file which has identical content.
+    pub identical: ConflictStrategy,
+
+    /// The strategy used when an image collides with an existing file which has different content.
+    pub different: ConflictStrategy,
+
+    /// The suffix appended to colliding file names by the "suffix" strategy, where `{n}` is replaced
+    /// with an incrementing counter (`{n:02}` pads it with zeros).
+    pub suffix: String,
+
+    /// The suffix appended to colliding file names by the "keep-both-with-hash" strategy, where
+    /// `{hash}` is replaced with the image's SHA-256 hash (`{hash:8}` keeps only its first 8 characters).
+    pub hash_suffix: String,
+
+    /// How colliding files are compared: byte for byte ("bytes"), or by only their encoded image
+    /// data ("image-data"), which treats JPEG and TIFF files whose metadata differs as identical.
+    pub compare: Comparison,
+}
+
+impl Default for ConflictConfig {
+    fn default() -> Self {
+        ConflictConfig {
+            identical: ConflictStrategy::Delete,
+            different: ConflictStrategy::Suffix,
+            suffix: " ({n})".to_string(),
+            hash_suffix: "-{hash:8}".to_string(),
+            compare: Comparison::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictStrategy {
+    /// Remove the incoming image, keeping the existing file.
+    Delete,
+    /// Import the incoming image under a new name using the configured suffix.
+    #[default]
+    Suffix,
+    /// Leave the incoming image where it is.
+    Skip,
+    /// Replace the existing file with the incoming image.
+    Overwrite,
+    /// Keep whichever of the two files is larger, removing the other.
+    KeepLarger,
+    /// Keep whichever of the two files was modified most recently, removing the other.
+    KeepNewer,
+    /// Import the incoming image under a new name which includes its hash.
+    KeepBothWithHash,
+    /// Move the incoming image into the quarantine directory.
+    Quarantine,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Comparison {
+    /// Files are only identical if every byte matches.
+    #[default]
+    Bytes,
+    /// JPEG and TIFF-based files are identical if their encoded image data matches, regardless of their metadata.
+    ImageData,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct QuarantineConfig {
+    /// The directory (relative to the target directory) into which quarantined images are moved.
+    pub directory: PathBuf,
+
+    /// The format used to name quarantined images, where `{date}` and `{time}` refer to when the image was quarantined.
+    pub template: String,
+
+    /// The number of days for which quarantined images are kept before `imgsort purge` removes them.
+    pub retention_days: u64,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        QuarantineConfig {
+            directory: PathBuf::from("_duplicates"),
+            template: "{date}/{name}".to_string(),
+            retention_days: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct JournalConfig {
+    /// If set to true, every change made during a run is recorded so that `imgsort undo` can reverse it,
+    /// and files which would be removed are kept alongside the journal instead.
+    pub enabled: bool,
+
+    /// The number of days for which journals (and the files they kept) are retained before `imgsort purge` removes them.
+    pub retention_days: u64,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        JournalConfig {
+            enabled: false,
+            retention_days: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RemovalConfig {
+    /// Whether removed files are deleted outright ("delete"), moved to the freedesktop.org trash
+    /// ("trash") or moved to the `#recycle` folder of their Synology shared folder ("recycle").
+    pub method: RemovalMethod,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovalMethod {
+    #[default]
+    Delete,
+    Trash,
+    Recycle,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct DedupeConfig {
+    /// Which copy of a duplicated file is kept.
+    pub keep: DedupeKeep,
+
+    /// What happens to the other copies of a duplicated file.
+    pub action: DedupeAction,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupeKeep {
+    /// Keep the copy with the shortest path.
+    #[default]
+    ShortestPath,
+    /// Keep the copy which is closest to where the import template would place it.
+    Template,
+    /// Keep the copy which was modified least recently.
+    Oldest,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupeAction {
+    /// Only report the duplicates which were found.
+    #[default]
+    Report,
+    /// Move the duplicates into the quarantine directory.
+    Quarantine,
+    /// Remove the duplicates using the configured removal method.
+    Delete,
+    /// Replace the duplicates with hardlinks to the copy which is kept.
+    Hardlink,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SimilarConfig {
+    /// If set to true, imported images are compared against the perceptual hashes of every image in the target library.
+    pub enabled: bool,
+
+    /// The maximum number of bits (out of 64) in which two perceptual hashes may differ for the images to be considered similar.
+    pub max_distance: u32,
+
+    /// Whether near-duplicates are only reported ("report") or imported into the similar directory for review ("route").
+    pub action: SimilarAction,
+
+    /// The directory (relative to the target directory) into which near-duplicates are routed.
+    pub directory: PathBuf,
+}
+
+impl Default for SimilarConfig {
+    fn default() -> Self {
+        SimilarConfig {
+            enabled: false,
+            max_distance: 6,
+            action: SimilarAction::default(),
+            directory: PathBuf::from("_similar"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SimilarAction {
+    #[default]
+    Report,
+    Route,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct WatchConfig {
+    /// The number of seconds for which a file must stop changing before it is imported.
+    pub debounce_seconds: u64,
+
+    /// The number of minutes between full rescans of the source directory, which pick up any
+    /// files whose events were missed.
+    pub rescan_minutes: u64,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            debounce_seconds: 5,
+            rescan_minutes: 60,
+        }
+    }
+}
+
+impl std::fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConflictStrategy::Delete => "delete",
+            ConflictStrategy::Suffix => "suffix",
+            ConflictStrategy::Skip => "skip",
+            ConflictStrategy::Overwrite => "overwrite",
+            ConflictStrategy::KeepLarger => "keep-larger",
+            ConflictStrategy::KeepNewer => "keep-newer",
+            ConflictStrategy::KeepBothWithHash => "keep-both-with-hash",
+            ConflictStrategy::Quarantine => "quarantine",
+        })
+    }
+}
+
 impl Config {
     pub fn load<S: AsRef<Path>>(source: S) -> Result<Config, crate::errors::Error> {
-        let content = std::fs::read_to_string(source).wrap_err_as_user(
+        let content = std::fs::read_to_string(source).wrap_user_err(
             "Failed to read your configuration file.",
             &["Make sure that the file exists and you have permission to access it."],
         )?;
 
-        toml::from_str(&content).wrap_err_as_user(
+        let config: Config = toml::from_str(&content).wrap_user_err(
             "Failed to parse your configuration file.",
             &["Make sure that your configuration file is valid TOML and matches the configuration schema."],
-        )
+        )?;
+
+        if config.conflict.different == ConflictStrategy::Delete {
+            return Err(human_errors::user(
+                "The 'delete' conflict strategy cannot be used for images which differ from the existing file.",
+                &["Choose a different strategy for `conflict.different`, such as 'suffix' or 'quarantine'."],
+            ));
+        }
+
+        if !config.conflict.suffix.contains("{n") {
+            return Err(human_errors::user(
+                "The conflict suffix must include an `{n}` counter.",
+                &["Update `conflict.suffix` to include `{n}`, for example ' ({n})' or '_{n:02}'."],
+            ));
+        }
+
+        Ok(config)
     }
 }
@@ -1,7 +1,28 @@
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
+use log::{info, warn};
 use sha2::Digest;
 
+use crate::config::{
+    Comparison, Config, ConflictConfig, ConflictStrategy, ImportMode, RemovalMethod,
+};
+use crate::hash_cache::HashCache;
+use crate::image_data;
+use crate::journal::{Action, Journal};
+use crate::overlay::Overlay;
+use crate::quarantine::Quarantine;
+use crate::template::{DataSource, TemplateContext, Value};
+use crate::trash;
+
+/// The extension given to the temporary files which imgsort creates itself, which are always
+/// moved into place regardless of the import mode.
+const TEMPORARY_EXTENSION: &str = "imgsort-tmp";
+
+/// The size of the chunks read from each file when comparing their contents.
+const COMPARISON_CHUNK_SIZE: usize = 64 * 1024;
+
 struct DigestWriter<D: Digest>(D);
 
 impl<D: Digest> std::io::Write for DigestWriter<D> {
@@ -15,49 +36,1290 @@ impl<D: Digest> std::io::Write for DigestWriter<D> {
     }
 }
 
-pub fn rename_no_conflict<S: AsRef<Path>, D: AsRef<Path>>(
-    source: S,
-    dest: D,
-) -> std::io::Result<PathBuf> {
-    let mut dest = dest.as_ref().to_path_buf();
-    let base_filename = dest.file_stem().unwrap().to_string_lossy().to_string();
-    let extension = dest.extension().unwrap().to_string_lossy().to_string();
-    let mut conflict_suffix = None;
+/// The outcome of placing a single file into the target library.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Placement {
+    /// The file was imported to this path.
+    Moved(PathBuf),
+    /// The file was imported to `path`, replacing the file which was already there (which was
+    /// moved into quarantine at `quarantined`, if set). `previous` is the file which held the
+    /// replaced content when the decision was made, which only still exists when simulating.
+    Replaced {
+        path: PathBuf,
+        previous: PathBuf,
+        quarantined: Option<PathBuf>,
+    },
+    /// The file was removed in favour of the existing file at this path.
+    Removed(PathBuf),
+    /// The file was left where it was.
+    Skipped,
+    /// The file was placed in quarantine at this path.
+    Quarantined(PathBuf),
+}
+
+/// Places files into the target library, resolving any collisions with existing files
+/// using the strategies configured in the `conflict` section of the configuration.
+#[derive(Clone)]
+pub struct ConflictManager<'a> {
+    config: &'a ConflictConfig,
+    mode: ImportMode,
+    removal: RemovalMethod,
+    cache: &'a HashCache,
+    quarantine: &'a Quarantine,
+    journal: Option<&'a Journal>,
+    overlay: Option<&'a Overlay>,
+    hashes: Option<&'a HashMap<PathBuf, String>>,
+}
+
+impl<'a> ConflictManager<'a> {
+    pub fn new(config: &'a Config, cache: &'a HashCache, quarantine: &'a Quarantine) -> Self {
+        ConflictManager {
+            config: &config.conflict,
+            mode: config.import_mode,
+            removal: config.removal.method,
+            cache,
+            quarantine,
+            journal: None,
+            overlay: None,
+            hashes: None,
+        }
+    }
+
+    /// Moves every source to its target, where every target shares the file stem of the first
+    /// (primary) target, resolving any collision with an existing file using the configured strategies.
+    ///
+    /// The group is treated as identical if every source is identical to (or absent from) the files
+    /// already present at its target, and as different otherwise. When comparing image data, an
+    /// identical source whose metadata is richer than the existing file's takes its place instead.
+    ///
+    /// Returns the placement of each source, in the same order as the provided moves.
+    pub fn rename_no_conflict(
+        &self,
+        moves: &[(PathBuf, PathBuf)],
+    ) -> std::io::Result<Vec<Placement>> {
+        self.place(moves, self.config.identical, self.config.different)
+    }
+
+    fn place(
+        &self,
+        moves: &[(PathBuf, PathBuf)],
+        identical: ConflictStrategy,
+        different: ConflictStrategy,
+    ) -> std::io::Result<Vec<Placement>> {
+        let Some((primary_source, _)) = moves.first() else {
+            return Ok(Vec::new());
+        };
+
+        let (base_filename, suffixes) = group_suffixes(moves);
+        let sources = || moves.iter().map(|(source, _)| source.as_path());
+
+        let mut hash_suffix: Option<String> = None;
+        let mut counter: Option<u32> = None;
+
+        loop {
+            let base = format!(
+                "{}{}{}",
+                base_filename,
+                hash_suffix.as_deref().unwrap_or_default(),
+                counter.map(|n| self.suffix(n)).unwrap_or_default()
+            );
+
+            let targets: Vec<PathBuf> = moves
+                .iter()
+                .zip(suffixes.iter())
+                .map(|((_, target), suffix)| target.with_file_name(format!("{}{}", base, suffix)))
+                .collect();
+
+            if targets.iter().all(|t| !self.exists(t)) {
+                let result = self.import_all(sources().zip(targets.iter()), false);
+                match result {
+                    Ok(()) => return Ok(targets.into_iter().map(Placement::Moved).collect()),
+                    // Another process created one of the targets after we checked for it, so we
+                    // resolve the conflict with that file instead.
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            let is_identical = self.exists(&targets[0])
+                && sources()
+                    .zip(targets.iter())
+                    .all(|(source, target)| !self.exists(target) || self.are_equal(source, target));
+
+            let richer = is_identical
+                && self.config.compare == Comparison::ImageData
+                && has_richer_metadata(&self.content(primary_source), &self.content(&targets[0]));
+
+            // Once we have started searching for a new name, any further collisions with different
+            // files simply continue that search.
+            let renaming = counter.is_some() || hash_suffix.is_some();
+            let strategy = match (is_identical, renaming) {
+                // Quarantine only retains sources which would otherwise be removed, so identical
+                // sources which the import mode leaves in place are simply skipped.
+                (true, _)
+                    if identical == ConflictStrategy::Quarantine
+                        && !richer
+                        && !self.consumes(primary_source) =>
+                {
+                    ConflictStrategy::Skip
+                }
+                (true, _) => identical,
+                (false, true) => ConflictStrategy::Suffix,
+                (false, false) => different,
+            };
+
+            if is_identical || !renaming {
+                info!(
+                    "conflict '{}' ({}): {}",
+                    targets[0].display(),
+                    match (is_identical, richer) {
+                        (true, true) => "identical, richer metadata",
+                        (true, false) => "identical",
+                        (false, _) => "different",
+                    },
+                    strategy
+                );
+            }
+
+            let result = match strategy {
+                ConflictStrategy::Suffix => {
+                    counter = Some(counter.map_or(1, |n| n + 1));
+                    continue;
+                }
+                ConflictStrategy::KeepBothWithHash if hash_suffix.is_none() => {
+                    hash_suffix = Some(self.hash_suffix(primary_source)?);
+                    continue;
+                }
+                ConflictStrategy::KeepBothWithHash => {
+                    counter = Some(counter.map_or(1, |n| n + 1));
+                    continue;
+                }
+                ConflictStrategy::Skip => Ok(vec![Placement::Skipped; moves.len()]),
+                // The existing file is the one which is redundant when the source has richer metadata.
+                ConflictStrategy::Delete if richer => self.replace_existing(moves, targets),
+                ConflictStrategy::Quarantine if richer => self.quarantine_existing(moves, targets),
+                ConflictStrategy::Delete => self.keep_existing(moves, &targets),
+                ConflictStrategy::Overwrite => self.replace_existing(moves, targets),
+                ConflictStrategy::KeepLarger => {
+                    let source = std::fs::metadata(self.content(primary_source))?.len();
+                    let existing = std::fs::metadata(self.content(&targets[0]))?.len();
+                    if source > existing {
+                        self.replace_existing(moves, targets)
+                    } else {
+                        self.keep_existing(moves, &targets)
+                    }
+                }
+                ConflictStrategy::KeepNewer => {
+                    let source = std::fs::metadata(self.content(primary_source))?.modified()?;
+                    let existing = std::fs::metadata(self.content(&targets[0]))?.modified()?;
+                    if source > existing {
+                        self.replace_existing(moves, targets)
+                    } else {
+                        self.keep_existing(moves, &targets)
+                    }
+                }
+                ConflictStrategy::Quarantine => self.quarantine(moves),
+            };
+
+            match result {
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Sets the way in which sources are brought into the library, overriding the configured import mode.
+    pub fn with_import_mode(mut self, mode: ImportMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Records every change made to the filesystem in the given journal, keeping files which would
+    /// otherwise be removed so that the run can be undone.
+    pub fn with_journal(mut self, journal: &'a Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Simulates every change against the given overlay instead of making it, so that audit mode
+    /// reaches the same decisions as a real run without touching the filesystem.
+    pub fn with_overlay(mut self, overlay: &'a Overlay) -> Self {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    /// Reuses the hashes which are already known for some of the sources when journaling them,
+    /// rather than hashing them again once they have been placed.
+    pub fn with_known_hashes(mut self, hashes: &'a HashMap<PathBuf, String>) -> Self {
+        self.hashes = Some(hashes);
+        self
+    }
+
+    /// Whether a file exists at `path`, taking any simulated changes into account.
+    pub fn exists(&self, path: &Path) -> bool {
+        match self.overlay {
+            Some(overlay) => overlay.exists(path),
+            None => path.exists(),
+        }
+    }
+
+    /// Records a file which would have been created at `path` if the changes weren't being simulated.
+    pub fn simulate_create(&self, path: &Path) {
+        if let Some(overlay) = self.overlay {
+            overlay.create(path);
+        }
+    }
+
+    /// Creates a directory (and any missing parents) into which files will be imported.
+    pub fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        match (self.overlay, self.journal) {
+            (Some(_), _) => Ok(()),
+            (None, Some(journal)) => journal.create_dir_all(path),
+            (None, None) => std::fs::create_dir_all(path),
+        }
+    }
+
+    /// Removes a file using the configured removal method, or keeps it in the journal if there is one.
+    pub fn remove(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(overlay) = self.overlay {
+            overlay.remove(path);
+            return Ok(());
+        }
+
+        match self.journal {
+            // The temporary files which imgsort creates itself have nothing worth restoring.
+            _ if is_temporary(path) => std::fs::remove_file(path),
+            Some(journal) => journal.keep(path, self.cache.hash(path)?).map(|_| ()),
+            None => trash::remove(path, self.removal).map(|_| ()),
+        }
+    }
+
+    /// The path at which a file would be placed in quarantine, before resolving any conflicts.
+    pub fn quarantine_path(&self, source: &Path) -> PathBuf {
+        self.quarantine.path_for(source)
+    }
+
+    /// Moves each source into the quarantine directory, keeping the group together under the
+    /// name given to the primary source by the quarantine template.
+    pub fn quarantine(&self, moves: &[(PathBuf, PathBuf)]) -> std::io::Result<Vec<Placement>> {
+        let primary = self.quarantine.path_for(&moves[0].0);
+        let (_, suffixes) = group_suffixes(moves);
+        let stem = primary.file_stem().unwrap().to_string_lossy().to_string();
+        let moves: Vec<(PathBuf, PathBuf)> = moves
+            .iter()
+            .zip(suffixes.iter())
+            .map(|((source, _), suffix)| {
+                (
+                    source.clone(),
+                    primary.with_file_name(format!("{}{}", stem, suffix)),
+                )
+            })
+            .collect();
+
+        self.create_dir_all(primary.parent().unwrap())?;
+
+        let placements = self.place(&moves, ConflictStrategy::Delete, ConflictStrategy::Suffix)?;
+        Ok(placements
+            .into_iter()
+            .map(|placement| match placement {
+                Placement::Moved(path) => {
+                    self.record_quarantined(&path);
+                    Placement::Quarantined(path)
+                }
+                placement => placement,
+            })
+            .collect())
+    }
+
+    /// Records when a file was placed in quarantine, so that it can be purged once it expires.
+    fn record_quarantined(&self, path: &Path) {
+        if self.overlay.is_some() {
+            return;
+        }
+
+        if let Err(e) = self.quarantine.record(path) {
+            warn!(
+                "Unable to record that '{}' was quarantined: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Moves the existing files at each target into quarantine, replacing them with their sources.
+    fn quarantine_existing(
+        &self,
+        moves: &[(PathBuf, PathBuf)],
+        targets: Vec<PathBuf>,
+    ) -> std::io::Result<Vec<Placement>> {
+        let existing: Vec<(PathBuf, PathBuf)> = targets
+            .iter()
+            .filter(|target| self.exists(target))
+            .map(|target| (target.clone(), target.clone()))
+            .collect();
+
+        let previous: Vec<Option<PathBuf>> = self.previous_contents(&targets);
+        let library = self.clone().with_import_mode(ImportMode::Move);
+        let mut quarantined = HashMap::new();
+        for (placement, (target, _)) in library.quarantine(&existing)?.into_iter().zip(&existing) {
+            if let Placement::Quarantined(path) = placement {
+                info!("mv '{}' '{}'", target.display(), path.display());
+                quarantined.insert(target.clone(), path);
+            }
+        }
+
+        self.import_all(
+            moves
+                .iter()
+                .map(|(source, _)| source.as_path())
+                .zip(targets.iter()),
+            false,
+        )?;
+        Ok(targets
+            .into_iter()
+            .zip(previous)
+            .map(|(path, previous)| match previous {
+                Some(previous) => Placement::Replaced {
+                    quarantined: quarantined.remove(&path),
+                    path,
+                    previous,
+                },
+                None => Placement::Moved(path),
+            })
+            .collect())
+    }
+
+    /// Determines whether a source is identical to an existing file, using the configured comparison.
+    fn are_equal(&self, source: &Path, existing: &Path) -> bool {
+        let (source, existing) = (&self.content(source), &self.content(existing));
+        if are_files_equal(source, existing, self.cache) {
+            return true;
+        }
+
+        match self.config.compare {
+            Comparison::Bytes => false,
+            Comparison::ImageData => match (image_data::hash(source), image_data::hash(existing)) {
+                (Ok(Some(a)), Ok(Some(b))) => a.hash == b.hash,
+                _ => false,
+            },
+        }
+    }
+
+    /// Imports each source to its target, undoing any imports which have already been completed
+    /// if one of them fails so that the group is never split up.
+    fn import_all<'s, 't, I: IntoIterator<Item = (&'s Path, &'t PathBuf)>>(
+        &self,
+        imports: I,
+        replace: bool,
+    ) -> std::io::Result<()> {
+        let mut completed: Vec<(&Path, &Path, Option<Action>)> = Vec::new();
+
+        for (source, target) in imports {
+            let result = self.import(source, target, replace).and_then(|_| {
+                completed.push((source, target.as_path(), None));
+
+                // Each file is journaled as soon as it has been placed, so that a run which is
+                // interrupted part way through a group can still be undone.
+                if let Some(journal) = self.journal {
+                    let action = self.imported(source, target)?;
+                    journal.record(&action)?;
+                    completed.last_mut().unwrap().2 = Some(action);
+                }
 
-    while dest.exists() {
-        if are_files_equal(source.as_ref(), dest.as_ref()) {
-            std::fs::remove_file(source.as_ref())?;
-            return Ok(dest);
+                Ok(())
+            });
+
+            if let Err(e) = result {
+                for (source, target, action) in completed.into_iter().rev() {
+                    let undone = match (self.overlay, self.consumes(source)) {
+                        (Some(overlay), consume) => {
+                            overlay.copy(target, source, consume);
+                            overlay.remove(target);
+                            Ok(())
+                        }
+                        (None, true) => move_file(target, source, false),
+                        (None, false) => std::fs::remove_file(target),
+                    };
+
+                    // Files which were moved back are journaled too, so that undoing the run
+                    // doesn't try to move them back a second time.
+                    if let (Ok(()), Some(journal), Some(Action::Move { hash, .. })) =
+                        (undone, self.journal, action)
+                    {
+                        let _ = journal.record(&Action::Move {
+                            source: target.to_path_buf(),
+                            target: source.to_path_buf(),
+                            hash,
+                        });
+                    }
+                }
+
+                return Err(e);
+            }
         }
 
-        conflict_suffix = match conflict_suffix {
-            Some(i) => Some(i + 1),
-            None => Some(1),
+        Ok(())
+    }
+
+    /// The journal entry for a file which has just been imported from `source` to `target`.
+    fn imported(&self, source: &Path, target: &Path) -> std::io::Result<Action> {
+        let hash = match self.hashes.and_then(|hashes| hashes.get(source)) {
+            Some(hash) => hash.clone(),
+            None => self.cache.hash(target)?,
         };
 
-        dest.set_file_name(format!(
-            "{} ({}).{}",
-            base_filename,
-            conflict_suffix.unwrap(),
-            extension
+        let (source, target) = (source.to_path_buf(), target.to_path_buf());
+        Ok(match self.consumes(&source) && !is_temporary(&source) {
+            true => Action::Move {
+                source,
+                target,
+                hash,
+            },
+            false => Action::Create {
+                source,
+                target,
+                hash,
+            },
+        })
+    }
+
+    /// Imports a single file using the configured import mode, falling back to a verified copy
+    /// where a hardlink or reflink is not possible. Existing files at `target` are only replaced
+    /// if `replace` is set.
+    fn import(&self, source: &Path, target: &Path, replace: bool) -> std::io::Result<()> {
+        if let Some(overlay) = self.overlay {
+            if !replace && overlay.exists(target) {
+                return Err(std::io::ErrorKind::AlreadyExists.into());
+            }
+
+            overlay.copy(source, target, self.consumes(source));
+            return Ok(());
+        }
+
+        if self.consumes(source) {
+            return move_file(source, target, replace);
+        }
+
+        match self.mode {
+            ImportMode::Move | ImportMode::Copy => {
+                via_temporary(target, replace, |temp| copy_verified(source, temp))
+            }
+            ImportMode::Hardlink => {
+                via_temporary(target, replace, |temp| {
+                    match std::fs::hard_link(source, temp) {
+                        Err(e) if is_cross_device(&e) => copy_verified(source, temp),
+                        result => result,
+                    }
+                })
+            }
+            ImportMode::Symlink => via_temporary(target, replace, |temp| symlink(source, temp)),
+            ImportMode::Reflink => via_temporary(target, replace, |temp| {
+                reflink(source, temp).or_else(|_| copy_verified(source, temp))
+            }),
+        }
+    }
+
+    /// Whether importing `source` removes it from its original location, which is always the
+    /// case for the temporary files which imgsort creates itself.
+    fn consumes(&self, source: &Path) -> bool {
+        self.mode == ImportMode::Move || is_temporary(source)
+    }
+
+    /// Keeps the existing files, importing any sources whose target is free and removing the rest
+    /// (unless the import mode leaves sources in place).
+    fn keep_existing(
+        &self,
+        moves: &[(PathBuf, PathBuf)],
+        targets: &[PathBuf],
+    ) -> std::io::Result<Vec<Placement>> {
+        let sources = || moves.iter().map(|(source, _)| source.as_path());
+        let placements = sources()
+            .zip(targets.iter())
+            .map(|(source, target)| match self.exists(target) {
+                true if self.consumes(source) => Placement::Removed(target.clone()),
+                true => Placement::Skipped,
+                false => Placement::Moved(target.clone()),
+            })
+            .collect();
+
+        self.import_all(
+            sources()
+                .zip(targets.iter())
+                .filter(|(_, target)| !self.exists(target)),
+            false,
+        )?;
+
+        for (source, target) in sources().zip(targets.iter()) {
+            if self.consumes(source) && self.exists(source) && self.exists(target) {
+                self.remove(source)?;
+            }
+        }
+
+        Ok(placements)
+    }
+
+    /// Replaces the existing files with their sources, removing the existing files first unless
+    /// they are simply being deleted (and don't need to be kept for the journal).
+    fn replace_existing(
+        &self,
+        moves: &[(PathBuf, PathBuf)],
+        targets: Vec<PathBuf>,
+    ) -> std::io::Result<Vec<Placement>> {
+        let previous = self.previous_contents(&targets);
+        if self.removal != RemovalMethod::Delete || self.journal.is_some() {
+            for target in targets.iter().filter(|target| self.exists(target)) {
+                self.remove(target)?;
+            }
+        }
+
+        self.import_all(
+            moves
+                .iter()
+                .map(|(source, _)| source.as_path())
+                .zip(targets.iter()),
+            true,
+        )?;
+        Ok(targets
+            .into_iter()
+            .zip(previous)
+            .map(|(path, previous)| match previous {
+                Some(previous) => Placement::Replaced {
+                    path,
+                    previous,
+                    quarantined: None,
+                },
+                None => Placement::Moved(path),
+            })
+            .collect())
+    }
+
+    /// The files which hold the content of each of the targets which exist, before they are replaced.
+    fn previous_contents(&self, targets: &[PathBuf]) -> Vec<Option<PathBuf>> {
+        targets
+            .iter()
+            .map(|target| self.exists(target).then(|| self.content(target)))
+            .collect()
+    }
+
+    /// Places each source exactly where an earlier (simulated) run decided that it should go,
+    /// without resolving any conflicts again, so that a reviewed plan is applied verbatim. Fails
+    /// with `AlreadyExists` if a destination which was free has since been taken.
+    pub fn replay(
+        &self,
+        moves: &[(PathBuf, PathBuf)],
+        placements: &[Placement],
+    ) -> std::io::Result<Vec<Placement>> {
+        let mut replace = false;
+        for placement in placements {
+            let Placement::Replaced {
+                path, quarantined, ..
+            } = placement
+            else {
+                continue;
+            };
+
+            replace = true;
+            match quarantined {
+                Some(quarantined) => {
+                    let library = self.clone().with_import_mode(ImportMode::Move);
+                    library.create_dir_all(quarantined.parent().unwrap())?;
+                    library.import_all([(path.as_path(), quarantined)], false)?;
+                    library.record_quarantined(quarantined);
+                    info!("mv '{}' '{}'", path.display(), quarantined.display());
+                }
+                None if self.removal != RemovalMethod::Delete || self.journal.is_some() => {
+                    self.remove(path)?
+                }
+                None => {}
+            }
+        }
+
+        let sources = || moves.iter().map(|(source, _)| source.as_path());
+        self.import_all(
+            sources()
+                .zip(placements)
+                .filter_map(|(source, placement)| match placement {
+                    Placement::Moved(path)
+                    | Placement::Replaced { path, .. }
+                    | Placement::Quarantined(path) => Some((source, path)),
+                    _ => None,
+                }),
+            replace,
+        )?;
+
+        for (source, placement) in sources().zip(placements) {
+            match placement {
+                Placement::Quarantined(path) => self.record_quarantined(path),
+                Placement::Removed(_) if self.consumes(source) && self.exists(source) => {
+                    self.remove(source)?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(placements.to_vec())
+    }
+
+    /// The real file holding the content which `path` has, taking any simulated changes into account.
+    pub fn content(&self, path: &Path) -> PathBuf {
+        self.overlay
+            .and_then(|overlay| overlay.content(path))
+            .unwrap_or_else(|| path.to_path_buf())
+    }
+
+    /// The hash of the content which `path` has, taking any simulated changes into account.
+    pub fn hash(&self, path: &Path) -> std::io::Result<String> {
+        self.cache.hash(self.content(path))
+    }
+
+    fn suffix(&self, n: u32) -> String {
+        TemplateContext::new(&self.config.suffix).render(&SuffixData { n, hash: "" })
+    }
+
+    fn hash_suffix(&self, path: &Path) -> std::io::Result<String> {
+        let hash = self.cache.hash(self.content(path))?;
+        Ok(
+            TemplateContext::new(&self.config.hash_suffix)
+                .render(&SuffixData { n: 0, hash: &hash }),
+        )
+    }
+}
+
+/// The values available to the conflict suffix templates, where `{n:02}` zero-pads the counter
+/// and `{hash:8}` truncates the hash.
+struct SuffixData<'a> {
+    n: u32,
+    hash: &'a str,
+}
+
+impl DataSource for SuffixData<'_> {
+    fn get(&self, key: &str) -> Option<Value<'_>> {
+        let (key, width) = match key.split_once(':') {
+            Some((key, width)) => (key, width.parse::<usize>().ok()?),
+            None => (key, 0),
+        };
+
+        match key {
+            "n" => Some(Value::Owned(format!("{:0width$}", self.n, width = width))),
+            "hash" if width > 0 => Some(Value::Borrowed(&self.hash[..width.min(self.hash.len())])),
+            "hash" => Some(Value::Borrowed(self.hash)),
+            _ => None,
+        }
+    }
+}
+
+/// Determines the stem shared by every target in the group (that of the primary target) and
+/// the suffix which follows it in each target's file name.
+fn group_suffixes(moves: &[(PathBuf, PathBuf)]) -> (String, Vec<String>) {
+    let primary = &moves[0].1;
+    let base_filename = primary.file_stem().unwrap().to_string_lossy().to_string();
+    let suffixes = moves
+        .iter()
+        .map(|(_, target)| {
+            let name = target.file_name().unwrap().to_string_lossy();
+            match name.strip_prefix(&base_filename) {
+                Some(suffix) => suffix.to_string(),
+                None => format!(".{}", target.extension().unwrap().to_string_lossy()),
+            }
+        })
+        .collect();
+
+    (base_filename, suffixes)
+}
+
+/// Replaces `duplicate` with a hardlink to `original`, which must have identical content.
+pub fn replace_with_hardlink(original: &Path, duplicate: &Path) -> std::io::Result<()> {
+    via_temporary(duplicate, true, |temp| std::fs::hard_link(original, temp))
+}
+
+/// Moves a file to its target, falling back to copying it when the target is on a different
+/// filesystem (where a rename is not possible).
+pub fn move_file(source: &Path, target: &Path, replace: bool) -> std::io::Result<()> {
+    match rename(source, target, replace) {
+        Err(e) if is_cross_device(&e) => {
+            via_temporary(target, replace, |temp| copy_verified(source, temp))?;
+            std::fs::remove_file(source)
+        }
+        result => result,
+    }
+}
+
+/// Renames a file, only replacing an existing file at `target` if `replace` is set. Otherwise the
+/// rename fails with [std::io::ErrorKind::AlreadyExists] if `target` exists, even if it appears
+/// while we are renaming the file.
+fn rename(source: &Path, target: &Path, replace: bool) -> std::io::Result<()> {
+    if replace {
+        return std::fs::rename(source, target);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = |path: &Path| {
+            std::ffi::CString::new(path.as_os_str().as_bytes())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+        };
+
+        let (from, to) = (c_path(source)?, c_path(target)?);
+        let result = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                from.as_ptr(),
+                libc::AT_FDCWD,
+                to.as_ptr(),
+                libc::RENAME_NOREPLACE,
+            )
+        };
+
+        if result == 0 {
+            return Ok(());
+        }
+
+        // Filesystems which don't support RENAME_NOREPLACE (and older kernels) fall back to linking.
+        let e = std::io::Error::last_os_error();
+        if !matches!(e.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOSYS)) {
+            return Err(e);
+        }
+    }
+
+    // Creating a hardlink fails if the target exists, so linking and then removing the original
+    // gives us the same guarantee on platforms and filesystems without an atomic primitive.
+    match std::fs::hard_link(source, target) {
+        Ok(()) => std::fs::remove_file(source),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists || is_cross_device(&e) => Err(e),
+        Err(_) if target.exists() => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", target.display()),
+        )),
+        // Filesystems without hardlinks (such as exFAT memory cards) are copied into a target which
+        // is created exclusively, since a plain rename would replace a file which appeared meanwhile.
+        Err(_) => {
+            copy_exclusive(source, target)?;
+            std::fs::remove_file(source)
+        }
+    }
+}
+
+fn is_cross_device(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::EXDEV) {
+        return true;
+    }
+
+    error.kind() == std::io::ErrorKind::CrossesDevices
+}
+
+/// The path of the temporary file used while preparing `target`, which sits alongside it so that
+/// it can be renamed into place atomically.
+pub fn temporary_path(target: &Path) -> PathBuf {
+    target.with_file_name(format!(
+        ".{}.{}",
+        target.file_name().unwrap_or_default().to_string_lossy(),
+        TEMPORARY_EXTENSION
+    ))
+}
+
+fn is_temporary(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == TEMPORARY_EXTENSION)
+}
+
+/// Creates `target` by writing it to a temporary file with `create` and then renaming that into place.
+fn via_temporary<F: FnOnce(&Path) -> std::io::Result<()>>(
+    target: &Path,
+    replace: bool,
+    create: F,
+) -> std::io::Result<()> {
+    let temp = temporary_path(target);
+    if let Err(e) = create(&temp).and_then(|_| rename(&temp, target, replace)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Copies a file, verifying that the copy matches the original and preserving its permissions and timestamps.
+fn copy_verified(source: &Path, target: &Path) -> std::io::Result<()> {
+    write_verified(source, target, std::fs::File::create(target)?)
+}
+
+/// Copies a file like [copy_verified], but fails with [std::io::ErrorKind::AlreadyExists] rather
+/// than replacing an existing file at `target`, and removes the copy if it can't be completed.
+fn copy_exclusive(source: &Path, target: &Path) -> std::io::Result<()> {
+    let to = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+
+    if let Err(e) = write_verified(source, target, to) {
+        let _ = std::fs::remove_file(target);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Writes the content of `source` to `to` (which was opened at `target`), verifying the result.
+fn write_verified(source: &Path, target: &Path, mut to: std::fs::File) -> std::io::Result<()> {
+    let mut from = std::fs::File::open(source)?;
+
+    let mut digest = sha2::Sha256::new();
+    let mut buf = vec![0u8; COMPARISON_CHUNK_SIZE];
+    loop {
+        let read = from.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+
+        digest.update(&buf[..read]);
+        to.write_all(&buf[..read])?;
+    }
+
+    preserve_metadata(&from, &to)?;
+    to.sync_all()?;
+    drop(to);
+
+    if base16ct::lower::encode_string(&digest.finalize()) != file_hash(target)? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "the copy of '{}' does not match the original",
+                source.display()
+            ),
         ));
     }
 
-    std::fs::rename(source, dest.as_path())?;
-    Ok(dest)
+    Ok(())
 }
 
-fn are_files_equal<P: AsRef<Path>>(a: P, b: P) -> bool {
-    match (file_hash(a), file_hash(b)) {
-        (Ok(a), Ok(b)) => a == b,
+/// Clones a file on filesystems which support copy-on-write (Btrfs, XFS), sharing its data blocks with the original.
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let from = std::fs::File::open(source)?;
+    let to = std::fs::File::create(target)?;
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    preserve_metadata(&from, &to)?;
+    to.sync_all()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
+
+fn symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    let source = std::fs::canonicalize(source)?;
+
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(source, target);
+
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(source, target);
+}
+
+fn preserve_metadata(from: &std::fs::File, to: &std::fs::File) -> std::io::Result<()> {
+    let metadata = from.metadata()?;
+    to.set_permissions(metadata.permissions())?;
+    to.set_times(
+        std::fs::FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?),
+    )
+}
+
+/// Determines whether two files have identical content, using the cheapest checks first:
+/// their sizes, any cached hashes, the chunks at the start and end of each file and finally
+/// a full byte-wise comparison which stops at the first difference.
+fn are_files_equal<P: AsRef<Path>>(a: P, b: P, cache: &HashCache) -> bool {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) if a.len() == b.len() => {}
+        _ => return false,
+    }
+
+    if let (Some(a), Some(b)) = (cache.cached(a), cache.cached(b)) {
+        return a == b;
+    }
+
+    compare_contents(a, b).unwrap_or(false)
+}
+
+/// Determines whether `source` carries more metadata than `existing`, an image with the same image data.
+fn has_richer_metadata(source: &Path, existing: &Path) -> bool {
+    match (image_data::hash(source), image_data::hash(existing)) {
+        (Ok(Some(source)), Ok(Some(existing))) => source.metadata_size > existing.metadata_size,
         _ => false,
     }
 }
 
-fn file_hash<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+fn compare_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let mut a = std::fs::File::open(a)?;
+    let mut b = std::fs::File::open(b)?;
+    let length = a.metadata()?.len();
+
+    let mut a_buf = vec![0u8; COMPARISON_CHUNK_SIZE];
+    let mut b_buf = vec![0u8; COMPARISON_CHUNK_SIZE];
+
+    // Files which differ usually do so in their headers (metadata) or trailers (embedded previews),
+    // so checking those first lets us skip reading the bulk of the file in most cases.
+    for offset in [0, length.saturating_sub(COMPARISON_CHUNK_SIZE as u64)] {
+        let size = COMPARISON_CHUNK_SIZE.min(length as usize);
+        a.seek(SeekFrom::Start(offset))?;
+        b.seek(SeekFrom::Start(offset))?;
+        a.read_exact(&mut a_buf[..size])?;
+        b.read_exact(&mut b_buf[..size])?;
+
+        if a_buf[..size] != b_buf[..size] {
+            return Ok(false);
+        }
+    }
+
+    a.seek(SeekFrom::Start(0))?;
+    b.seek(SeekFrom::Start(0))?;
+    loop {
+        let read = a.read(&mut a_buf)?;
+        if read == 0 {
+            return Ok(true);
+        }
+
+        b.read_exact(&mut b_buf[..read])?;
+        if a_buf[..read] != b_buf[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+pub fn file_hash<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
     let mut writer = DigestWriter(sha2::Sha256::new());
     let mut file = std::fs::File::open(path)?;
     std::io::copy(&mut file, &mut writer)?;
     let hash = writer.0.finalize();
     Ok(base16ct::lower::encode_string(&hash))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("source")).unwrap();
+        std::fs::create_dir_all(dir.path().join("target")).unwrap();
+        dir
+    }
+
+    use crate::file_group::FileGroup;
+
+    #[test]
+    fn test_rename_group_with_conflict() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/DSC0001.ARW"), "raw").unwrap();
+        std::fs::write(dir.join("source/DSC0001.xmp"), "xmp").unwrap();
+        std::fs::write(dir.join("target/photo.xmp"), "other").unwrap();
+
+        let group = FileGroup::new(dir.join("source/DSC0001.ARW"))
+            .with_companions(vec![dir.join("source/DSC0001.xmp")]);
+
+        let config = Config {
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        let cache = HashCache::disabled();
+        let quarantine =
+            Quarantine::new(&config, TemplateContext::new(&config.quarantine.template));
+        let written = ConflictManager::new(&config, &cache, &quarantine)
+            .rename_no_conflict(&group.moves(dir.join("target/photo.ARW")))
+            .unwrap();
+        assert_eq!(
+            written,
+            vec![
+                Placement::Moved(dir.join("target/photo (1).ARW")),
+                Placement::Moved(dir.join("target/photo (1).xmp"))
+            ]
+        );
+        assert!(!dir.join("source/DSC0001.ARW").exists());
+        assert!(!dir.join("source/DSC0001.xmp").exists());
+    }
+
+    #[test]
+    fn test_conflict_strategies() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/a.jpg"), "same").unwrap();
+        std::fs::write(dir.join("source/b.jpg"), "new").unwrap();
+        std::fs::write(dir.join("target/photo.jpg"), "same").unwrap();
+
+        let mut config = Config {
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        config.conflict.identical = ConflictStrategy::Skip;
+        config.conflict.suffix = "_{n:02}".to_string();
+        let cache = HashCache::disabled();
+        let quarantine =
+            Quarantine::new(&config, TemplateContext::new(&config.quarantine.template));
+        let manager = ConflictManager::new(&config, &cache, &quarantine);
+
+        let moves =
+            |name: &str| vec![(dir.join("source").join(name), dir.join("target/photo.jpg"))];
+        assert_eq!(
+            manager.rename_no_conflict(&moves("a.jpg")).unwrap(),
+            vec![Placement::Skipped]
+        );
+        assert!(dir.join("source/a.jpg").exists());
+
+        assert_eq!(
+            manager.rename_no_conflict(&moves("b.jpg")).unwrap(),
+            vec![Placement::Moved(dir.join("target/photo_01.jpg"))]
+        );
+    }
+
+    #[test]
+    fn test_simulated_conflicts() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/a.jpg"), "a").unwrap();
+        std::fs::write(dir.join("source/b.jpg"), "b").unwrap();
+        std::fs::write(dir.join("source/c.jpg"), "a").unwrap();
+
+        let mut config = Config {
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        config.conflict.identical = ConflictStrategy::Delete;
+        let cache = HashCache::disabled();
+        let quarantine =
+            Quarantine::new(&config, TemplateContext::new(&config.quarantine.template));
+        let overlay = Overlay::default();
+        let manager = ConflictManager::new(&config, &cache, &quarantine).with_overlay(&overlay);
+
+        let target = dir.join("target/photo.jpg");
+        for (source, placement) in [
+            ("a.jpg", Placement::Moved(target.clone())),
+            ("b.jpg", Placement::Moved(dir.join("target/photo (1).jpg"))),
+            ("c.jpg", Placement::Removed(target.clone())),
+        ] {
+            let moves = vec![(dir.join("source").join(source), target.clone())];
+            assert_eq!(manager.rename_no_conflict(&moves).unwrap(), vec![placement]);
+        }
+
+        assert!(!manager.exists(&dir.join("source/c.jpg")));
+        assert!(dir.join("source/a.jpg").exists() && dir.join("source/c.jpg").exists());
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn test_journal_each_placed_file() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/a.jpg"), "a").unwrap();
+
+        let config = Config {
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        let cache = HashCache::disabled();
+        let quarantine =
+            Quarantine::new(&config, TemplateContext::new(&config.quarantine.template));
+        let journal = Journal::begin(dir.join("target")).unwrap();
+        let run_id = journal.run_id().to_string();
+        let manager = ConflictManager::new(&config, &cache, &quarantine).with_journal(&journal);
+
+        // The second file of the group is missing, so the first is moved back once it fails.
+        let moves = vec![
+            (dir.join("source/a.jpg"), dir.join("target/a.jpg")),
+            (dir.join("source/a.xmp"), dir.join("target/a.xmp")),
+        ];
+        assert!(manager.rename_no_conflict(&moves).is_err());
+        assert!(dir.join("source/a.jpg").exists());
+        journal.finish().unwrap();
+
+        let actions = crate::journal::load(dir.join("target"), &run_id).unwrap();
+        assert_eq!(actions.len(), 2);
+        assert!(crate::journal::check(&actions).is_empty());
+    }
+
+    #[test]
+    fn test_journal_known_hashes() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/a.jpg"), "a").unwrap();
+
+        let config = Config {
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        let cache = HashCache::disabled();
+        let quarantine =
+            Quarantine::new(&config, TemplateContext::new(&config.quarantine.template));
+        let journal = Journal::begin(dir.join("target")).unwrap();
+        let run_id = journal.run_id().to_string();
+        let hashes = HashMap::from([(dir.join("source/a.jpg"), "known".to_string())]);
+        let manager = ConflictManager::new(&config, &cache, &quarantine)
+            .with_journal(&journal)
+            .with_known_hashes(&hashes);
+
+        let moves = vec![(dir.join("source/a.jpg"), dir.join("target/a.jpg"))];
+        manager.rename_no_conflict(&moves).unwrap();
+        journal.finish().unwrap();
+
+        let actions = crate::journal::load(dir.join("target"), &run_id).unwrap();
+        assert_eq!(
+            actions,
+            vec![Action::Move {
+                source: dir.join("source/a.jpg"),
+                target: dir.join("target/a.jpg"),
+                hash: "known".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_compare_image_data() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let scan = [0xFF, 0xDA, 0x00, 0x03, 0x01, 0x12, 0x34, 0xFF, 0xD9];
+        let plain = [&[0xFF, 0xD8][..], &scan].concat();
+        let tagged = [&[0xFF, 0xD8, 0xFF, 0xFE, 0x00, 0x04, b'h', b'i'][..], &scan].concat();
+        std::fs::write(dir.join("source/tagged.jpg"), &tagged).unwrap();
+        std::fs::write(dir.join("target/photo.jpg"), &plain).unwrap();
+
+        let mut config = Config {
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        config.conflict.identical = ConflictStrategy::Delete;
+        config.conflict.compare = Comparison::ImageData;
+        let cache = HashCache::disabled();
+        let quarantine =
+            Quarantine::new(&config, TemplateContext::new(&config.quarantine.template));
+        let manager = ConflictManager::new(&config, &cache, &quarantine);
+
+        let moves = vec![(dir.join("source/tagged.jpg"), dir.join("target/photo.jpg"))];
+        assert_eq!(
+            manager.rename_no_conflict(&moves).unwrap(),
+            vec![Placement::Replaced {
+                path: dir.join("target/photo.jpg"),
+                previous: dir.join("target/photo.jpg"),
+                quarantined: None
+            }]
+        );
+        assert_eq!(std::fs::read(dir.join("target/photo.jpg")).unwrap(), tagged);
+
+        std::fs::write(dir.join("source/plain.jpg"), &plain).unwrap();
+        let moves = vec![(dir.join("source/plain.jpg"), dir.join("target/photo.jpg"))];
+        assert_eq!(
+            manager.rename_no_conflict(&moves).unwrap(),
+            vec![Placement::Removed(dir.join("target/photo.jpg"))]
+        );
+        assert_eq!(std::fs::read(dir.join("target/photo.jpg")).unwrap(), tagged);
+    }
+
+    #[test]
+    fn test_copy_verified() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/a.jpg"), "image").unwrap();
+        let modified = std::fs::metadata(dir.join("source/a.jpg"))
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        via_temporary(&dir.join("target/a.jpg"), false, |temp| {
+            copy_verified(&dir.join("source/a.jpg"), temp)
+        })
+        .unwrap();
+        assert_eq!(std::fs::read(dir.join("target/a.jpg")).unwrap(), b"image");
+        assert!(!temporary_path(&dir.join("target/a.jpg")).exists());
+        assert_eq!(
+            std::fs::metadata(dir.join("target/a.jpg"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            modified
+        );
+    }
+
+    #[test]
+    fn test_rename_no_replace() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/a.jpg"), "new").unwrap();
+        std::fs::write(dir.join("target/a.jpg"), "existing").unwrap();
+
+        let error =
+            rename(&dir.join("source/a.jpg"), &dir.join("target/a.jpg"), false).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(dir.join("source/a.jpg")).unwrap(), b"new");
+        assert_eq!(
+            std::fs::read(dir.join("target/a.jpg")).unwrap(),
+            b"existing"
+        );
+
+        rename(&dir.join("source/a.jpg"), &dir.join("target/b.jpg"), false).unwrap();
+        assert!(!dir.join("source/a.jpg").exists());
+        assert_eq!(std::fs::read(dir.join("target/b.jpg")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn test_copy_exclusive() {
+        let temp = test_dir();
+        let dir = temp.path();
+        std::fs::write(dir.join("source/a.jpg"), "new").unwrap();
+        std::fs::write(dir.join("target/a.jpg"), "existing").unwrap();
+
+        let error =
+            copy_exclusive(&dir.join("source/a.jpg"), &dir.join("target/a.jpg")).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            std::fs::read(dir.join("target/a.jpg")).unwrap(),
+            b"existing"
+        );
+
+        copy_exclusive(&dir.join("source/a.jpg"), &dir.join("target/b.jpg")).unwrap();
+        assert_eq!(std::fs::read(dir.join("source/a.jpg")).unwrap(), b"new");
+        assert_eq!(std::fs::read(dir.join("target/b.jpg")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn test_are_files_equal() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let content = vec![7u8; COMPARISON_CHUNK_SIZE * 3];
+        let mut different = content.clone();
+        different[COMPARISON_CHUNK_SIZE + 1] = 8;
+
+        std::fs::write(dir.join("source/a"), &content).unwrap();
+        std::fs::write(dir.join("source/b"), &content).unwrap();
+        std::fs::write(dir.join("source/c"), &different).unwrap();
+        std::fs::write(dir.join("source/d"), &content[1..]).unwrap();
+
+        let cache = HashCache::disabled();
+        assert!(are_files_equal(
+            dir.join("source/a"),
+            dir.join("source/b"),
+            &cache
+        ));
+        assert!(!are_files_equal(
+            dir.join("source/a"),
+            dir.join("source/c"),
+            &cache
+        ));
+        assert!(!are_files_equal(
+            dir.join("source/a"),
+            dir.join("source/d"),
+            &cache
+        ));
+    }
+}
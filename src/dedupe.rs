@@ -0,0 +1,287 @@
+//! Detection of duplicate files which already exist within a library, regardless of how they got
+//! there, and the removal of all but one copy of each.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::{
+    config::{DedupeAction, DedupeKeep, RemovalMethod},
+    conflict_manager::{self, ConflictManager, Placement},
+    hash_cache::HashCache,
+    store::STATE_DIR,
+    trash,
+};
+
+/// Finds every group of files within `root` which have identical content, excluding files for
+/// which `ignore` returns true and treating files which are hardlinked together as a single file.
+/// Files are only hashed if another file shares their size.
+pub fn find_duplicates<F: Fn(&Path) -> bool>(
+    root: &Path,
+    cache: &HashCache,
+    ignore: F,
+) -> Vec<Vec<PathBuf>> {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut seen = HashSet::new();
+    for entry in walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != STATE_DIR)
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| !ignore(entry.path()))
+    {
+        if let Ok(metadata) = entry.metadata() {
+            // Files which are already hardlinked together don't take up any additional space.
+            if let Some(id) = file_id(&metadata) {
+                if !seen.insert(id) {
+                    continue;
+                }
+            }
+
+            by_size
+                .entry(metadata.len())
+                .or_default()
+                .push(entry.into_path());
+        }
+    }
+
+    let mut groups = Vec::new();
+    for (size, paths) in by_size {
+        if size == 0 || paths.len() < 2 {
+            continue;
+        }
+
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            match cache.hash(&path) {
+                Ok(hash) => by_hash.entry(hash).or_default().push(path),
+                Err(e) => warn!("Unable to hash '{}': {}", path.display(), e),
+            }
+        }
+
+        groups.extend(by_hash.into_values().filter(|group| group.len() > 1));
+    }
+
+    for group in groups.iter_mut() {
+        group.sort();
+    }
+
+    groups.sort();
+    groups
+}
+
+/// Picks the copy within a group which should be kept, where `expected` returns the path at
+/// which imgsort's template would place a file (if it is an image).
+pub fn pick_keeper<F: Fn(&Path) -> Option<PathBuf>>(
+    group: &[PathBuf],
+    rule: DedupeKeep,
+    expected: F,
+) -> usize {
+    let shortest = |path: &PathBuf| (path.as_os_str().len(), path.clone());
+
+    let keeper = match rule {
+        DedupeKeep::ShortestPath => group.iter().enumerate().min_by_key(|(_, p)| shortest(p)),
+        DedupeKeep::Template => group.iter().enumerate().min_by_key(|(_, p)| {
+            // Files already where the template would put them win, followed by those in the right folder.
+            let score = match expected(p) {
+                Some(expected) if expected == **p => 0,
+                Some(expected) if expected.parent() == p.parent() => 1,
+                _ => 2,
+            };
+
+            (score, shortest(p))
+        }),
+        DedupeKeep::Oldest => group.iter().enumerate().min_by_key(|(_, p)| {
+            let modified = std::fs::metadata(p).and_then(|m| m.modified()).ok();
+            (modified.is_none(), modified, shortest(p))
+        }),
+    };
+
+    keeper.map(|(i, _)| i).unwrap_or_default()
+}
+
+/// Applies the given action to each of the duplicates of `keeper`, returning the number of bytes
+/// reclaimed by replacing duplicates with hardlinks.
+pub fn resolve(
+    keeper: &Path,
+    duplicates: &[PathBuf],
+    action: DedupeAction,
+    conflicts: &ConflictManager,
+    removal: RemovalMethod,
+    audit: bool,
+) -> std::io::Result<u64> {
+    let mut reclaimed = 0;
+    for duplicate in duplicates {
+        info!(
+            "duplicate '{}' of '{}'",
+            duplicate.display(),
+            keeper.display()
+        );
+
+        match action {
+            DedupeAction::Report => {}
+            DedupeAction::Delete => {
+                info!("rm '{}'", duplicate.display());
+                if !audit {
+                    trash::remove(duplicate, removal)?;
+                }
+            }
+            DedupeAction::Quarantine if audit => info!(
+                "mv '{}' '{}'",
+                duplicate.display(),
+                conflicts.quarantine_path(duplicate).display()
+            ),
+            DedupeAction::Quarantine => {
+                let moves = [(duplicate.clone(), duplicate.clone())];
+                for placement in conflicts.quarantine(&moves)? {
+                    match placement {
+                        Placement::Quarantined(path) => {
+                            info!("mv '{}' '{}'", duplicate.display(), path.display())
+                        }
+                        Placement::Removed(_) => info!("rm '{}'", duplicate.display()),
+                        _ => {}
+                    }
+                }
+            }
+            DedupeAction::Hardlink => {
+                if !audit {
+                    reclaimed += hardlink(keeper, duplicate)?;
+                } else if can_hardlink(keeper, duplicate) {
+                    info!("ln '{}' '{}'", keeper.display(), duplicate.display());
+                    reclaimed += std::fs::metadata(duplicate)?.len();
+                }
+            }
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+/// Replaces `duplicate` with a hardlink to `keeper`, returning the number of bytes reclaimed.
+///
+/// The shared file takes on the newest modification time of the two, so that tools which
+/// watch for changes in either location don't miss an edit.
+fn hardlink(keeper: &Path, duplicate: &Path) -> std::io::Result<u64> {
+    if !can_hardlink(keeper, duplicate) {
+        return Ok(0);
+    }
+
+    let keeper_metadata = std::fs::metadata(keeper)?;
+    let duplicate_metadata = std::fs::metadata(duplicate)?;
+    let modified = keeper_metadata
+        .modified()?
+        .max(duplicate_metadata.modified()?);
+
+    info!("ln '{}' '{}'", keeper.display(), duplicate.display());
+    conflict_manager::replace_with_hardlink(keeper, duplicate)?;
+    std::fs::File::open(keeper)?.set_modified(modified)?;
+
+    // The duplicate's data is only freed if nothing else links to it.
+    match link_count(&duplicate_metadata) {
+        1 => Ok(duplicate_metadata.len()),
+        _ => Ok(0),
+    }
+}
+
+/// Determines whether two files can be hardlinked together, which requires them to be on the
+/// same filesystem and not already be links to the same file.
+#[cfg(unix)]
+fn can_hardlink(keeper: &Path, duplicate: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let (Ok(a), Ok(b)) = (std::fs::metadata(keeper), std::fs::metadata(duplicate)) else {
+        return false;
+    };
+
+    if a.dev() != b.dev() {
+        warn!(
+            "Unable to hardlink '{}' to '{}' because they are on different filesystems.",
+            duplicate.display(),
+            keeper.display()
+        );
+        return false;
+    }
+
+    a.ino() != b.ino()
+}
+
+#[cfg(not(unix))]
+fn can_hardlink(_keeper: &Path, _duplicate: &Path) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &std::fs::Metadata) -> u64 {
+    1
+}
+
+/// Formats a number of bytes for display, using binary units.
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{} B", bytes),
+                unit => format!("{:.1} {}", size, unit),
+            };
+        }
+
+        size /= 1024.0;
+    }
+
+    format!("{:.1} TiB", size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicates() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("2024/album")).unwrap();
+        std::fs::write(dir.join("2024/a.jpg"), "image").unwrap();
+        std::fs::write(dir.join("2024/album/a copy.jpg"), "image").unwrap();
+        std::fs::write(dir.join("2024/b.jpg"), "other").unwrap();
+
+        let groups = find_duplicates(dir, &HashCache::disabled(), |_| false);
+        assert_eq!(
+            groups,
+            vec![vec![
+                dir.join("2024/a.jpg"),
+                dir.join("2024/album/a copy.jpg")
+            ]]
+        );
+
+        let keeper = pick_keeper(&groups[0], DedupeKeep::Template, |_| {
+            Some(dir.join("2024/album/a.jpg"))
+        });
+        assert_eq!(keeper, 1);
+
+        let reclaimed = hardlink(&groups[0][1], &groups[0][0]).unwrap();
+        assert_eq!(reclaimed, 5);
+        assert_eq!(hardlink(&groups[0][1], &groups[0][0]).unwrap(), 0);
+        assert!(find_duplicates(dir, &HashCache::disabled(), |_| false).is_empty());
+    }
+}
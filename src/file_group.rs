@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+/// A primary image together with the companion files (sidecars, paired images, etc.)
+/// which must be moved alongside it and share its final name.
+#[derive(Debug, Clone)]
+pub struct FileGroup {
+    pub primary: PathBuf,
+    pub companions: Vec<PathBuf>,
+}
+
+impl FileGroup {
+    pub fn new<P: Into<PathBuf>>(primary: P) -> Self {
+        FileGroup {
+            primary: primary.into(),
+            companions: Vec::new(),
+        }
+    }
+
+    pub fn with_companions<I: IntoIterator<Item = PathBuf>>(mut self, companions: I) -> Self {
+        for companion in companions {
+            if companion != self.primary && !self.companions.contains(&companion) {
+                self.companions.push(companion);
+            }
+        }
+
+        self
+    }
+
+    /// All of the files in this group, starting with the primary.
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.primary.as_path()).chain(self.companions.iter().map(|p| p.as_path()))
+    }
+
+    /// The initial (source, target) pairs used to move this group so that the primary
+    /// lands at `dest` and every companion shares its file stem.
+    pub fn moves<D: AsRef<Path>>(&self, dest: D) -> Vec<(PathBuf, PathBuf)> {
+        let dest = dest.as_ref();
+        let base = dest
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.files()
+            .map(|file| {
+                let target = if file == self.primary {
+                    dest.to_path_buf()
+                } else {
+                    dest.with_file_name(format!("{}{}", base, self.suffix_of(file)))
+                };
+
+                (file.to_path_buf(), target)
+            })
+            .collect()
+    }
+
+    /// The portion of a file's name which follows the primary's file stem, so that
+    /// `DSC0001.ARW.xmp` in the `DSC0001.ARW` group has a suffix of `.ARW.xmp`.
+    ///
+    /// Files which do not share the primary's stem fall back to using their extension.
+    pub fn suffix_of<P: AsRef<Path>>(&self, path: P) -> String {
+        let path = path.as_ref();
+        let stem = self
+            .primary
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = path
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        match name.strip_prefix(&stem) {
+            Some(suffix) if suffix.is_empty() || suffix.starts_with('.') => suffix.to_string(),
+            _ => path
+                .extension()
+                .map(|ext| format!(".{}", ext.to_string_lossy()))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_suffix_of() {
+        let group = FileGroup::new("import/DSC0001.ARW").with_companions(vec![
+            PathBuf::from("import/DSC0001.xmp"),
+            PathBuf::from("import/DSC0001.ARW.xmp"),
+            PathBuf::from("import/DSC00012.xmp"),
+        ]);
+
+        assert_eq!(group.suffix_of(&group.primary), ".ARW");
+        assert_eq!(group.suffix_of("import/DSC0001.xmp"), ".xmp");
+        assert_eq!(group.suffix_of("import/DSC0001.ARW.xmp"), ".ARW.xmp");
+        assert_eq!(group.suffix_of("import/DSC00012.xmp"), ".xmp");
+    }
+}
@@ -0,0 +1,314 @@
+//! A persistent index which records a value (such as a content hash) for every file in the target
+//! library, along with the size and modification time each file had when its value was computed,
+//! so that reopening the index only needs to examine the files which have changed since.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hash_cache::file_info,
+    pipeline,
+    store::{format_path, parse_path, Store, STATE_DIR},
+};
+
+/// A value which can be stored in a [FileIndex].
+pub trait Value: Sized {
+    fn format(&self) -> String;
+    fn parse(value: &str) -> Option<Self>;
+}
+
+impl Value for String {
+    fn format(&self) -> String {
+        self.clone()
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry<V> {
+    value: V,
+    size: u64,
+    modified: u64,
+}
+
+impl<V> Entry<V> {
+    /// Whether the entry was recorded for a file with the given size and modification time.
+    fn matches(&self, size: u64, modified: u64) -> bool {
+        self.size == size && self.modified == modified
+    }
+}
+
+pub struct FileIndex<V> {
+    root: PathBuf,
+    store: Store,
+    entries: HashMap<PathBuf, Entry<V>>,
+}
+
+impl<V: Value> FileIndex<V> {
+    /// Opens the index called `name` for the library at `root`, bringing it up to date with the
+    /// files on disk.
+    ///
+    /// Only files which have been added or modified since the index was last updated are passed to
+    /// `compute` on a pool of `threads` workers (and are left out if it returns `None`), and files
+    /// for which `ignore` returns true are excluded from the index entirely. The refreshed index is
+    /// only persisted once [FileIndex::save] is called.
+    pub fn open<P, F, C>(
+        root: P,
+        name: &str,
+        threads: usize,
+        ignore: F,
+        compute: C,
+    ) -> std::io::Result<Self>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path) -> bool,
+        C: Fn(&Path) -> Option<V> + Sync,
+        V: Send,
+    {
+        let mut index = FileIndex {
+            root: root.as_ref().to_path_buf(),
+            store: Store::new(&root, name),
+            entries: HashMap::new(),
+        };
+
+        let mut previous = index.load()?;
+        let mut changed = Vec::new();
+
+        for entry in walkdir::WalkDir::new(&index.root)
+            .into_iter()
+            .filter_entry(|e| e.file_name() != STATE_DIR)
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter(|entry| !ignore(entry.path()))
+        {
+            let Ok(relative) = entry.path().strip_prefix(&index.root) else {
+                continue;
+            };
+
+            let Ok((size, modified)) = file_info(entry.path()) else {
+                continue;
+            };
+
+            match previous.remove(relative) {
+                Some(e) if e.matches(size, modified) => {
+                    index.entries.insert(relative.to_path_buf(), e);
+                }
+                _ => changed.push((relative.to_path_buf(), size, modified)),
+            }
+        }
+
+        pipeline::ordered(
+            &changed,
+            threads,
+            |(relative, _, _)| compute(&index.root.join(relative)),
+            |(relative, size, modified), value| {
+                if let Some(value) = value {
+                    index.entries.insert(
+                        relative.clone(),
+                        Entry {
+                            value,
+                            size: *size,
+                            modified: *modified,
+                        },
+                    );
+                }
+
+                Ok::<_, std::io::Error>(())
+            },
+        )?;
+
+        Ok(index)
+    }
+
+    /// The root of the library which is indexed.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Every file in the index (relative to the root of the library) along with its value.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &V)> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| (path.as_path(), &entry.value))
+    }
+
+    /// The value recorded for a file, given its path relative to the root of the library.
+    pub fn get(&self, relative: &Path) -> Option<&V> {
+        self.entries.get(relative).map(|entry| &entry.value)
+    }
+
+    /// Records a file which has been added to the library, persisting it immediately. The value is
+    /// only computed if the file has changed since it was last recorded.
+    ///
+    /// Returns the path of the file relative to the root of the library, if it was recorded.
+    pub fn insert<P, C>(&mut self, path: P, compute: C) -> std::io::Result<Option<PathBuf>>
+    where
+        P: AsRef<Path>,
+        C: FnOnce() -> std::io::Result<V>,
+    {
+        let path = path.as_ref();
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return Ok(None);
+        };
+
+        let (size, modified) = file_info(path)?;
+        if matches!(self.entries.get(relative), Some(e) if e.matches(size, modified)) {
+            return Ok(None);
+        }
+
+        let entry = Entry {
+            value: compute()?,
+            size,
+            modified,
+        };
+
+        let record = format_entry(relative, &entry);
+        self.store
+            .append(&record.iter().map(String::as_str).collect::<Vec<_>>())?;
+
+        self.entries.insert(relative.to_path_buf(), entry);
+        Ok(Some(relative.to_path_buf()))
+    }
+
+    /// Records a file which an audit would have added to the library, without persisting it
+    /// (or reading the file, which doesn't exist yet).
+    ///
+    /// Returns the path of the file relative to the root of the library, if it was recorded.
+    pub fn simulate_insert<P: AsRef<Path>>(&mut self, path: P, value: V) -> Option<PathBuf> {
+        let relative = path.as_ref().strip_prefix(&self.root).ok()?;
+        let entry = Entry {
+            value,
+            size: 0,
+            modified: 0,
+        };
+
+        self.entries.insert(relative.to_path_buf(), entry);
+        Some(relative.to_path_buf())
+    }
+
+    fn load(&self) -> std::io::Result<HashMap<PathBuf, Entry<V>>> {
+        // Later records take precedence, since the index is appended to as files are imported.
+        Ok(self
+            .store
+            .load(4)?
+            .iter()
+            .filter_map(|record| parse_entry(record))
+            .collect())
+    }
+
+    /// Rewrites the index with only the current entries, replacing the old index atomically.
+    ///
+    /// Records which other processes appended since the index was opened are kept as long as the
+    /// files they describe haven't changed since.
+    pub fn save(&self) -> std::io::Result<()> {
+        self.store.update(4, |records| {
+            let appended: Vec<(PathBuf, Entry<V>)> = records
+                .iter()
+                .filter_map(|record| parse_entry(record))
+                .filter(|(path, entry)| {
+                    let known = self.entries.get(path);
+                    !known.is_some_and(|e| e.matches(entry.size, entry.modified))
+                })
+                .filter(|(path, entry)| {
+                    let info = file_info(self.root.join(path));
+                    info.is_ok_and(|(size, modified)| entry.matches(size, modified))
+                })
+                .collect();
+
+            let mut entries: HashMap<&Path, &Entry<V>> = self
+                .entries
+                .iter()
+                .map(|(path, entry)| (path.as_path(), entry))
+                .collect();
+            entries.extend(appended.iter().map(|(path, entry)| (path.as_path(), entry)));
+
+            Ok(entries
+                .into_iter()
+                .map(|(path, entry)| format_entry(path, entry))
+                .collect())
+        })
+    }
+}
+
+fn format_entry<V: Value>(path: &Path, entry: &Entry<V>) -> Vec<String> {
+    vec![
+        entry.value.format(),
+        entry.size.to_string(),
+        entry.modified.to_string(),
+        format_path(path),
+    ]
+}
+
+fn parse_entry<V: Value>(record: &[String]) -> Option<(PathBuf, Entry<V>)> {
+    let [value, size, modified, path] = record else {
+        return None;
+    };
+
+    Some((
+        parse_path(path),
+        Entry {
+            value: V::parse(value)?,
+            size: size.parse().ok()?,
+            modified: modified.parse().ok()?,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_roundtrip() {
+        let entry = Entry {
+            value: "abc123".to_string(),
+            size: 42,
+            modified: 1_700_000_000_000_000_000,
+        };
+
+        let record = format_entry(Path::new("2024/photo\twith tab.jpg"), &entry);
+        let (path, parsed): (PathBuf, Entry<String>) = parse_entry(&record).unwrap();
+        assert_eq!(path, PathBuf::from("2024/photo\twith tab.jpg"));
+        assert_eq!(parsed.value, "abc123");
+        assert_eq!(parsed.size, 42);
+        assert_eq!(parsed.modified, 1_700_000_000_000_000_000);
+    }
+
+    #[test]
+    fn test_open_only_computes_changed_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("a.jpg"), "a").unwrap();
+        std::fs::write(dir.join("b.jpg"), "b").unwrap();
+
+        let compute = |path: &Path| Some(std::fs::read_to_string(path).unwrap());
+        let index: FileIndex<String> = FileIndex::open(dir, "test", 2, |_| false, compute).unwrap();
+        index.save().unwrap();
+
+        std::fs::write(dir.join("b.jpg"), "changed").unwrap();
+        let computed = std::sync::Mutex::new(Vec::new());
+        let index: FileIndex<String> = FileIndex::open(
+            dir,
+            "test",
+            2,
+            |_| false,
+            |path| {
+                computed.lock().unwrap().push(path.to_path_buf());
+                compute(path)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(computed.into_inner().unwrap(), vec![dir.join("b.jpg")]);
+        assert_eq!(index.get(Path::new("a.jpg")).map(String::as_str), Some("a"));
+        assert_eq!(
+            index.get(Path::new("b.jpg")).map(String::as_str),
+            Some("changed")
+        );
+    }
+}
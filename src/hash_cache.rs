@@ -0,0 +1,268 @@
+//! A persistent cache of file content hashes, which avoids rehashing large files (like RAW
+//! images on a NAS) every time they are involved in a conflict.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    config::HashCacheBackend,
+    store::{format_path, parse_path, Store},
+};
+
+const CACHE_FILE: &str = "hashes";
+
+/// The extended attribute in which a file's hash is stored when using the `xattr` backend.
+#[cfg(unix)]
+const XATTR_NAME: &str = "user.imgsort.sha256";
+
+/// The identity and version of a file, which must match for a cached hash to be reused.
+#[derive(Debug, Clone, PartialEq)]
+struct Fingerprint {
+    key: String,
+    size: u64,
+    modified: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    fingerprint: Fingerprint,
+    hash: String,
+    path: PathBuf,
+}
+
+pub struct HashCache {
+    backend: Backend,
+    read_only: bool,
+}
+
+enum Backend {
+    Disabled,
+    Database {
+        store: Store,
+        entries: Mutex<HashMap<String, Entry>>,
+    },
+    #[cfg(unix)]
+    ExtendedAttributes,
+}
+
+impl HashCache {
+    /// Opens the configured hash cache, storing any database within the `target` library.
+    ///
+    /// A read-only cache will reuse existing hashes but never record new ones.
+    pub fn open<P: AsRef<Path>>(
+        backend: HashCacheBackend,
+        target: P,
+        read_only: bool,
+    ) -> std::io::Result<Self> {
+        let backend = match backend {
+            HashCacheBackend::None => Backend::Disabled,
+            HashCacheBackend::Database => {
+                let store = Store::new(target, CACHE_FILE);
+                let entries = store
+                    .load(5)?
+                    .iter()
+                    .filter_map(|record| parse_entry(record))
+                    .map(|entry| (entry.fingerprint.key.clone(), entry))
+                    .collect();
+
+                Backend::Database {
+                    store,
+                    entries: Mutex::new(entries),
+                }
+            }
+            #[cfg(unix)]
+            HashCacheBackend::Xattr => Backend::ExtendedAttributes,
+            #[cfg(not(unix))]
+            HashCacheBackend::Xattr => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Extended attributes are not supported on this platform.",
+                ))
+            }
+        };
+
+        Ok(HashCache { backend, read_only })
+    }
+
+    /// A cache which always computes hashes from scratch.
+    #[cfg(test)]
+    pub fn disabled() -> Self {
+        HashCache {
+            backend: Backend::Disabled,
+            read_only: true,
+        }
+    }
+
+    /// Gets the cached hash of a file's content without computing it, if the file has not changed.
+    pub fn cached<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        let path = path.as_ref();
+
+        match &self.backend {
+            Backend::Disabled => None,
+            Backend::Database { entries, .. } => {
+                let fingerprint = fingerprint(path).ok()?;
+                entries
+                    .lock()
+                    .unwrap()
+                    .get(&fingerprint.key)
+                    .filter(|entry| entry.fingerprint == fingerprint)
+                    .map(|entry| entry.hash.clone())
+            }
+            #[cfg(unix)]
+            Backend::ExtendedAttributes => {
+                let fingerprint = fingerprint(path).ok()?;
+                xattr::get(path, XATTR_NAME)
+                    .ok()
+                    .flatten()
+                    .and_then(|value| parse_xattr(&value))
+                    .filter(|(size, modified, _)| {
+                        *size == fingerprint.size && *modified == fingerprint.modified
+                    })
+                    .map(|(_, _, hash)| hash)
+            }
+        }
+    }
+
+    /// Gets the SHA-256 hash of a file's content, reusing a cached value if the file has not changed.
+    pub fn hash<P: AsRef<Path>>(&self, path: P) -> std::io::Result<String> {
+        let path = path.as_ref();
+        if let Some(hash) = self.cached(path) {
+            return Ok(hash);
+        }
+
+        match &self.backend {
+            Backend::Disabled => crate::conflict_manager::file_hash(path),
+            Backend::Database { store, entries } => {
+                let fingerprint = fingerprint(path)?;
+                let hash = crate::conflict_manager::file_hash(path)?;
+                if !self.read_only {
+                    let entry = Entry {
+                        fingerprint,
+                        hash: hash.clone(),
+                        path: path.to_path_buf(),
+                    };
+
+                    // Failing to update the cache only costs us a rehash later, so it isn't fatal.
+                    let record = format_entry(&entry);
+                    let _ = store.append(&record.iter().map(String::as_str).collect::<Vec<_>>());
+                    entries
+                        .lock()
+                        .unwrap()
+                        .insert(entry.fingerprint.key.clone(), entry);
+                }
+
+                Ok(hash)
+            }
+            #[cfg(unix)]
+            Backend::ExtendedAttributes => {
+                let fingerprint = fingerprint(path)?;
+                let hash = crate::conflict_manager::file_hash(path)?;
+                if !self.read_only {
+                    // Read-only media (like memory cards) can't store attributes, which is fine.
+                    let value = format!("{}:{}:{}", fingerprint.size, fingerprint.modified, hash);
+                    let _ = xattr::set(path, XATTR_NAME, value.as_bytes());
+                }
+
+                Ok(hash)
+            }
+        }
+    }
+
+    /// Compacts the cache database, discarding entries for files which have since changed or been removed.
+    pub fn save(&self) -> std::io::Result<()> {
+        let Backend::Database { store, entries } = &self.backend else {
+            return Ok(());
+        };
+
+        if self.read_only {
+            return Ok(());
+        }
+
+        // Entries which other processes appended since the cache was opened are kept too.
+        let entries = entries.lock().unwrap();
+        store.update(5, |records| {
+            let parsed: Vec<Entry> = records.iter().filter_map(|r| parse_entry(r)).collect();
+            let current: HashMap<&str, &Entry> = parsed
+                .iter()
+                .chain(entries.values())
+                .filter(|entry| matches!(fingerprint(&entry.path), Ok(f) if f == entry.fingerprint))
+                .map(|entry| (entry.fingerprint.key.as_str(), entry))
+                .collect();
+
+            Ok(current.into_values().map(format_entry).collect())
+        })
+    }
+}
+
+/// Gets the size and modification time (in nanoseconds since the UNIX epoch) of a file.
+pub fn file_info<P: AsRef<Path>>(path: P) -> std::io::Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+
+    Ok((metadata.len(), modified))
+}
+
+fn fingerprint(path: &Path) -> std::io::Result<Fingerprint> {
+    let (size, modified) = file_info(path)?;
+
+    #[cfg(unix)]
+    let key = {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path)?;
+        format!("{}:{}", metadata.dev(), metadata.ino())
+    };
+
+    #[cfg(not(unix))]
+    let key = path.display().to_string();
+
+    Ok(Fingerprint {
+        key,
+        size,
+        modified,
+    })
+}
+
+fn format_entry(entry: &Entry) -> Vec<String> {
+    vec![
+        entry.fingerprint.key.clone(),
+        entry.fingerprint.size.to_string(),
+        entry.fingerprint.modified.to_string(),
+        entry.hash.clone(),
+        format_path(&entry.path),
+    ]
+}
+
+fn parse_entry(record: &[String]) -> Option<Entry> {
+    let [key, size, modified, hash, path] = record else {
+        return None;
+    };
+
+    Some(Entry {
+        fingerprint: Fingerprint {
+            key: key.clone(),
+            size: size.parse().ok()?,
+            modified: modified.parse().ok()?,
+        },
+        hash: hash.clone(),
+        path: parse_path(path),
+    })
+}
+
+#[cfg(unix)]
+fn parse_xattr(value: &[u8]) -> Option<(u64, u64, String)> {
+    let value = std::str::from_utf8(value).ok()?;
+    let mut parts = value.splitn(3, ':');
+    let size = parts.next()?.parse().ok()?;
+    let modified = parts.next()?.parse().ok()?;
+    let hash = parts.next()?.to_string();
+
+    Some((size, modified, hash))
+}
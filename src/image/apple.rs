@@ -0,0 +1,63 @@
+//! Parsing for the MakerNote block written by Apple devices, which links the still
+//! image of a Live Photo to its video through a shared content identifier.
+
+use crate::isobmff::{read_u16, read_u32};
+
+const HEADER: &[u8] = b"Apple iOS\0";
+const CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+
+/// Extracts the Live Photo content identifier from an Apple MakerNote, if one is present.
+pub fn content_identifier(maker_note: &[u8]) -> Option<String> {
+    if !maker_note.starts_with(HEADER) || maker_note.get(12..14)? != b"MM" {
+        return None;
+    }
+
+    // The IFD follows the header and uses offsets relative to the start of the MakerNote.
+    let count = read_u16(maker_note, 14)? as usize;
+    (0..count).find_map(|i| {
+        let entry = 16 + i * 12;
+        if read_u16(maker_note, entry)? != CONTENT_IDENTIFIER_TAG {
+            return None;
+        }
+
+        let length = read_u32(maker_note, entry + 4)? as usize;
+        let value = if length <= 4 {
+            maker_note.get(entry + 8..entry + 8 + length)?
+        } else {
+            let offset = read_u32(maker_note, entry + 8)? as usize;
+            maker_note.get(offset..offset + length)?
+        };
+
+        let value = String::from_utf8_lossy(value)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        (!value.is_empty()).then_some(value)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_content_identifier() {
+        let identifier = b"0A1B2C3D-0000-4000-8000-000000000001\0";
+        let mut note = Vec::from(HEADER);
+        note.extend_from_slice(&[0x00, 0x01]);
+        note.extend_from_slice(b"MM");
+        note.extend_from_slice(&1u16.to_be_bytes());
+        note.extend_from_slice(&CONTENT_IDENTIFIER_TAG.to_be_bytes());
+        note.extend_from_slice(&2u16.to_be_bytes());
+        note.extend_from_slice(&(identifier.len() as u32).to_be_bytes());
+        note.extend_from_slice(&32u32.to_be_bytes());
+        note.extend_from_slice(&0u32.to_be_bytes());
+        note.extend_from_slice(identifier);
+
+        assert_eq!(
+            content_identifier(&note).as_deref(),
+            Some("0A1B2C3D-0000-4000-8000-000000000001")
+        );
+        assert_eq!(content_identifier(b"Nikon\0"), None);
+    }
+}
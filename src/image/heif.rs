@@ -0,0 +1,224 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use human_errors::ResultExt;
+
+use crate::isobmff::{self, read_u16, read_u32, read_uint};
+
+use super::{ImageLoader, Metadata};
+
+const SUPPORTED_FILE_TYPES: [&str; 3] = ["HEIC", "HEIF", "HIF"];
+
+pub struct HeifImage {}
+
+impl ImageLoader for HeifImage {
+    fn supports(extension: &str) -> bool {
+        SUPPORTED_FILE_TYPES.contains(&extension)
+    }
+
+    fn load(path: &Path) -> Result<Metadata<'_>, human_errors::Error> {
+        let exif = read_exif(path)
+            .wrap_system_err(
+                format!("Could not load image file '{}'.", path.display()),
+                &["Make sure that you are attempting to load a valid image file format."],
+            )?
+            .ok_or_else(|| {
+                human_errors::user(
+                    format!("Could not load image metadata from '{}'.", path.display()),
+                    &["Make sure that the image file contains the necessary metadata."],
+                )
+            })?;
+
+        let img = rexif::parse_buffer_quiet(&exif).0.wrap_system_err(
+            format!("Could not parse the EXIF metadata in '{}'.", path.display()),
+            &["Make sure that the image file contains valid EXIF metadata."],
+        )?;
+
+        Ok(super::rexif::metadata_from_exif(path, img))
+    }
+}
+
+/// Locates the `Exif` item within a HEIF file and returns the TIFF structure it contains.
+fn read_exif(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    let Some(meta) = isobmff::read_top_level(path, b"meta")? else {
+        return Ok(None);
+    };
+
+    let meta = isobmff::Atom {
+        kind: *b"meta",
+        data: &meta,
+    };
+
+    let Some(item_id) = meta
+        .full_children()
+        .find(|atom| &atom.kind == b"iinf")
+        .and_then(|iinf| find_exif_item(iinf.data))
+    else {
+        return Ok(None);
+    };
+
+    let Some(extents) = meta
+        .full_children()
+        .find(|atom| &atom.kind == b"iloc")
+        .and_then(|iloc| find_item_extents(iloc.data, item_id))
+    else {
+        return Ok(None);
+    };
+
+    let mut file = std::fs::File::open(path)?;
+    let mut data = Vec::new();
+    for (offset, length) in extents {
+        file.seek(SeekFrom::Start(offset))?;
+        file.by_ref().take(length).read_to_end(&mut data)?;
+    }
+
+    // The Exif item starts with the offset of the TIFF header within the remaining payload.
+    let Some(tiff_offset) = read_u32(&data, 0) else {
+        return Ok(None);
+    };
+
+    Ok(data
+        .get(4 + tiff_offset as usize..)
+        .map(|tiff| tiff.to_vec()))
+}
+
+fn find_exif_item(iinf: &[u8]) -> Option<u32> {
+    let version = *iinf.first()?;
+    let entries = if version == 0 { 6 } else { 8 };
+
+    isobmff::atoms(iinf.get(entries..)?)
+        .filter(|atom| &atom.kind == b"infe")
+        .find_map(|infe| {
+            let (item_id, item_type) = match infe.data.first()? {
+                2 => (read_u16(infe.data, 4)? as u32, infe.data.get(8..12)?),
+                3 => (read_u32(infe.data, 4)?, infe.data.get(10..14)?),
+                _ => return None,
+            };
+
+            (item_type == b"Exif").then_some(item_id)
+        })
+}
+
+fn find_item_extents(iloc: &[u8], item_id: u32) -> Option<Vec<(u64, u64)>> {
+    let version = *iloc.first()?;
+    let offset_size = (iloc.get(4)? >> 4) as usize;
+    let length_size = (iloc.get(4)? & 0x0f) as usize;
+    let base_offset_size = (iloc.get(5)? >> 4) as usize;
+    let index_size = if version > 0 {
+        (iloc.get(5)? & 0x0f) as usize
+    } else {
+        0
+    };
+
+    let (item_count, mut pos) = if version < 2 {
+        (read_u16(iloc, 6)? as u32, 8)
+    } else {
+        (read_u32(iloc, 6)?, 10)
+    };
+
+    for _ in 0..item_count {
+        let id = if version < 2 {
+            pos += 2;
+            read_u16(iloc, pos - 2)? as u32
+        } else {
+            pos += 4;
+            read_u32(iloc, pos - 4)?
+        };
+
+        let construction_method = if version > 0 {
+            pos += 2;
+            read_u16(iloc, pos - 2)? & 0x0f
+        } else {
+            0
+        };
+
+        // Skip the data reference index
+        pos += 2;
+
+        let base_offset = read_uint(iloc, pos, base_offset_size)?;
+        pos += base_offset_size;
+
+        let extent_count = read_u16(iloc, pos)?;
+        pos += 2;
+
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            pos += index_size;
+            let offset = read_uint(iloc, pos, offset_size)?;
+            pos += offset_size;
+            let length = read_uint(iloc, pos, length_size)?;
+            pos += length_size;
+
+            extents.push((base_offset.checked_add(offset)?, length));
+        }
+
+        if id == item_id {
+            // Only items stored directly in the file (rather than in an `idat` atom) are supported.
+            return (construction_method == 0).then_some(extents);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn atom(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        [&(data.len() as u32 + 8).to_be_bytes()[..], kind, data].concat()
+    }
+
+    /// Builds a HEIF file holding a single Exif item, stored after the `meta` atom.
+    fn heif_with_exif(tiff: &[u8]) -> Vec<u8> {
+        let ftyp = atom(b"ftyp", b"heic\0\0\0\0mif1heic");
+
+        // A version 2 item info entry: item ID 1, no protection, item type "Exif".
+        let infe = atom(
+            b"infe",
+            &[&[2, 0, 0, 0, 0, 1, 0, 0][..], b"Exif", b"\0"].concat(),
+        );
+        let iinf = atom(b"iinf", &[&[0, 0, 0, 0, 0, 1][..], &infe].concat());
+
+        let payload = [&[0, 0, 0, 0][..], tiff].concat();
+        let iloc = |offset: u32| {
+            let mut data = vec![0, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 0, 0, 1];
+            data.extend(offset.to_be_bytes());
+            data.extend((payload.len() as u32).to_be_bytes());
+            atom(b"iloc", &data)
+        };
+
+        let meta = |offset: u32| {
+            atom(
+                b"meta",
+                &[
+                    &[0, 0, 0, 0][..],
+                    &atom(b"hdlr", &[0; 24]),
+                    &iinf,
+                    &iloc(offset),
+                ]
+                .concat(),
+            )
+        };
+
+        let offset = (ftyp.len() + meta(0).len()) as u32;
+        [ftyp, meta(offset), payload].concat()
+    }
+
+    #[test]
+    fn test_read_exif() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("IMG_0001.HEIC");
+
+        std::fs::write(&path, heif_with_exif(b"MM\0\x2a\0\0\0\x08")).unwrap();
+        assert_eq!(
+            read_exif(&path).unwrap(),
+            Some(b"MM\0\x2a\0\0\0\x08".to_vec())
+        );
+
+        std::fs::write(&path, atom(b"ftyp", b"heic")).unwrap();
+        assert_eq!(read_exif(&path).unwrap(), None);
+    }
+}
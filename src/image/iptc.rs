@@ -0,0 +1,194 @@
+//! Parsing for the IPTC-IIM metadata which press and agency workflows store in the
+//! Photoshop (APP13) segment of JPEG images.
+
+use crate::isobmff::{read_u16, read_u32};
+
+const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+
+/// The escape sequence used in dataset 1:90 to declare that text is encoded as UTF-8.
+const UTF8_CHARSET: &[u8] = b"\x1b%G";
+
+#[derive(Debug, Default, Clone)]
+pub struct Iptc {
+    pub object_name: Option<String>,
+    pub keywords: Vec<String>,
+    pub byline: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub country: Option<String>,
+    pub headline: Option<String>,
+    pub copyright: Option<String>,
+    pub caption: Option<String>,
+}
+
+/// Extracts the IPTC-IIM metadata from the APP13 segment of a JPEG image.
+pub fn parse_jpeg(data: &[u8]) -> Option<Iptc> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+
+        // Start of scan, after which only entropy coded image data follows.
+        if marker == 0xda {
+            break;
+        }
+
+        let length = read_u16(data, pos + 2)? as usize;
+        let segment = data.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xed {
+            if let Some(iptc) = segment
+                .strip_prefix(PHOTOSHOP_HEADER)
+                .and_then(find_iptc_resource)
+            {
+                return Some(parse(iptc));
+            }
+        }
+
+        pos += 2 + length;
+    }
+
+    None
+}
+
+/// Finds the IPTC-NAA record within a sequence of Photoshop `8BIM` image resources.
+fn find_iptc_resource(mut data: &[u8]) -> Option<&[u8]> {
+    while data.starts_with(b"8BIM") {
+        let id = read_u16(data, 4)?;
+
+        // The resource name is a Pascal string, padded to an even length.
+        let name_length = *data.get(6)? as usize;
+        let name_size = (name_length + 2) & !1;
+
+        let size = read_u32(data, 6 + name_size)? as usize;
+        let start = 10 + name_size;
+        let resource = data.get(start..start + size)?;
+
+        if id == IPTC_RESOURCE_ID {
+            return Some(resource);
+        }
+
+        data = data.get(start + ((size + 1) & !1)..)?;
+    }
+
+    None
+}
+
+/// Parses a sequence of IIM datasets, decoding text according to the character set in dataset 1:90.
+pub fn parse(mut data: &[u8]) -> Iptc {
+    let mut datasets = Vec::new();
+    while data.len() >= 5 && data[0] == 0x1c {
+        let (record, dataset) = (data[1], data[2]);
+        let (length, header) = match read_u16(data, 3).unwrap_or_default() {
+            // Extended datasets store the number of bytes in their length field instead.
+            length if length & 0x8000 != 0 => {
+                let size = (length & 0x7fff) as usize;
+                let length = data
+                    .get(5..5 + size)
+                    .map(|bytes| bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize));
+                match length {
+                    Some(length) => (length, 5 + size),
+                    None => break,
+                }
+            }
+            length => (length as usize, 5),
+        };
+
+        let Some(end) = header.checked_add(length) else {
+            break;
+        };
+
+        let Some(value) = data.get(header..end) else {
+            break;
+        };
+
+        datasets.push((record, dataset, value));
+        data = &data[end..];
+    }
+
+    let utf8 = datasets
+        .iter()
+        .any(|(record, dataset, value)| *record == 1 && *dataset == 90 && *value == UTF8_CHARSET);
+    let decode = |value: &[u8]| -> String {
+        let text = if utf8 {
+            String::from_utf8_lossy(value).to_string()
+        } else {
+            // Without a declared character set, IIM text is conventionally treated as ISO-8859-1.
+            value.iter().map(|b| *b as char).collect()
+        };
+
+        text.trim_end_matches('\0').trim().to_string()
+    };
+
+    let mut iptc = Iptc::default();
+    for (_, dataset, value) in datasets.into_iter().filter(|(record, _, _)| *record == 2) {
+        let value = decode(value);
+        if value.is_empty() {
+            continue;
+        }
+
+        match dataset {
+            5 => iptc.object_name = Some(value),
+            25 => iptc.keywords.push(value),
+            80 => iptc.byline = iptc.byline.or(Some(value)),
+            90 => iptc.city = Some(value),
+            95 => iptc.state = Some(value),
+            101 => iptc.country = Some(value),
+            105 => iptc.headline = Some(value),
+            116 => iptc.copyright = Some(value),
+            120 => iptc.caption = Some(value),
+            _ => {}
+        }
+    }
+
+    iptc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dataset(record: u8, dataset: u8, value: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x1c, record, dataset];
+        data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = [
+            dataset(1, 90, UTF8_CHARSET),
+            dataset(2, 80, "Zoë Smith".as_bytes()),
+            dataset(2, 25, b"harbour"),
+            dataset(2, 25, b"boats"),
+            dataset(2, 90, "Malmö".as_bytes()),
+        ]
+        .concat();
+
+        let iptc = parse(&data);
+        assert_eq!(iptc.byline.as_deref(), Some("Zoë Smith"));
+        assert_eq!(iptc.city.as_deref(), Some("Malmö"));
+        assert_eq!(iptc.keywords, vec!["harbour", "boats"]);
+    }
+
+    #[test]
+    fn test_parse_latin1() {
+        let iptc = parse(&dataset(2, 90, b"Malm\xf6"));
+        assert_eq!(iptc.city.as_deref(), Some("Malmö"));
+    }
+
+    #[test]
+    fn test_parse_malformed_extended_length() {
+        let mut data = dataset(2, 80, b"Zoe Smith");
+        data.extend_from_slice(&[0x1c, 2, 120, 0x80, 0x08]);
+        data.extend_from_slice(&[0xff; 8]);
+
+        let iptc = parse(&data);
+        assert_eq!(iptc.byline.as_deref(), Some("Zoe Smith"));
+        assert_eq!(iptc.caption, None);
+    }
+}
@@ -3,6 +3,8 @@ use std::path::Path;
 
 use crate::template::DataSource;
 
+use super::iptc::Iptc;
+
 #[derive(Debug)]
 pub struct Metadata<'a> {
     pub path: &'a Path,
@@ -13,11 +15,20 @@ pub struct Metadata<'a> {
     pub date_time: Option<String>,
     pub image_number: Option<u32>,
 
+    pub description: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+
     pub owner_name: Option<String>,
     pub camera_make: Option<String>,
     pub camera_model: Option<String>,
     pub lens_make: Option<String>,
     pub lens_model: Option<String>,
+
+    pub iptc: Iptc,
+
+    pub pair_role: Option<&'static str>,
+    pub content_identifier: Option<String>,
 }
 
 impl<'a> Metadata<'a> {
@@ -31,14 +42,30 @@ impl<'a> Metadata<'a> {
             date_time: None,
             image_number: Self::parse_image_number_from_path(path),
 
+            description: None,
+            latitude: None,
+            longitude: None,
+
             owner_name: None,
             camera_make: None,
             camera_model: None,
             lens_make: None,
             lens_model: None,
+
+            iptc: Iptc::default(),
+
+            pair_role: None,
+            content_identifier: None,
         }
     }
 
+    /// Attaches the image's IPTC metadata, which is also used as a fallback for the EXIF artist and copyright.
+    pub fn set_iptc(&mut self, iptc: Iptc) {
+        self.artist = self.artist.take().or_else(|| iptc.byline.clone());
+        self.copyright = self.copyright.take().or_else(|| iptc.copyright.clone());
+        self.iptc = iptc;
+    }
+
     pub fn validate(&self) -> Result<(), crate::errors::Error> {
         if !matches!(self.get("year"), Some(y) if y.as_str().trim().len() == 4) {
             Err(human_errors::user(
@@ -58,7 +85,7 @@ impl<'a> Metadata<'a> {
             // Takes any trailing numbers and parses them as a single contiguous value
             stem.chars()
                 .rev()
-                .take_while(|c| c.is_digit(10))
+                .take_while(|c| c.is_ascii_digit())
                 .collect::<String>()
                 .chars()
                 .rev()
@@ -72,7 +99,7 @@ impl<'a> Metadata<'a> {
 }
 
 impl DataSource for Metadata<'_> {
-    fn get(&self, key: &str) -> Option<crate::template::Value> {
+    fn get(&self, key: &str) -> Option<crate::template::Value<'_>> {
         match key {
             "name" => self
                 .path
@@ -85,6 +112,10 @@ impl DataSource for Metadata<'_> {
 
             "number" => self.image_number.map(|n| n.to_string().into()),
 
+            "description" => self.description.as_ref().map(|s| cleanup_string(s).into()),
+            "gps.latitude" => self.latitude.map(|v| format!("{:.6}", v).into()),
+            "gps.longitude" => self.longitude.map(|v| format!("{:.6}", v).into()),
+
             "owner.name" => self.owner_name.as_ref().map(|s| cleanup_string(s).into()),
 
             "camera.make" => self.camera_make.as_ref().map(|s| cleanup_string(s).into()),
@@ -93,6 +124,30 @@ impl DataSource for Metadata<'_> {
             "lens.make" => self.lens_make.as_ref().map(|s| cleanup_string(s).into()),
             "lens.model" => self.lens_model.as_ref().map(|s| cleanup_string(s).into()),
 
+            "iptc.title" => self
+                .iptc
+                .object_name
+                .as_ref()
+                .map(|s| cleanup_string(s).into()),
+            "iptc.byline" => self.iptc.byline.as_ref().map(|s| cleanup_string(s).into()),
+            "iptc.headline" => self
+                .iptc
+                .headline
+                .as_ref()
+                .map(|s| cleanup_string(s).into()),
+            "iptc.caption" => self.iptc.caption.as_ref().map(|s| cleanup_string(s).into()),
+            "iptc.keywords" => Some(cleanup_string(&self.iptc.keywords.join(", ")).into()),
+            "iptc.city" => self.iptc.city.as_ref().map(|s| cleanup_string(s).into()),
+            "iptc.state" => self.iptc.state.as_ref().map(|s| cleanup_string(s).into()),
+            "iptc.country" => self.iptc.country.as_ref().map(|s| cleanup_string(s).into()),
+            "iptc.copyright" => self
+                .iptc
+                .copyright
+                .as_ref()
+                .map(|s| cleanup_string(s).into()),
+
+            "pair.role" => self.pair_role.map(|r| r.into()),
+
             "year" => self
                 .date_time
                 .as_deref()
@@ -130,7 +185,7 @@ impl DataSource for Metadata<'_> {
 fn cleanup_string(s: &str) -> String {
     s.trim()
         .ascii_chars()
-        .filter_map(|c| c)
+        .flatten()
         .filter(|c| !c.contains('\0'))
         .collect()
 }
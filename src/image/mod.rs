@@ -1,35 +1,53 @@
 use std::path::Path;
 
-use crate::template::TemplateContext;
-
+mod apple;
+mod heif;
+mod iptc;
 mod metadata;
 mod rawler;
 mod rexif;
+mod takeout;
 
 pub use metadata::Metadata;
+pub use takeout::{find_sidecar as find_takeout_sidecar, format_timestamp};
 
 pub trait ImageLoader {
     fn supports(extension: &str) -> bool;
-    fn render<P: AsRef<Path>>(ctx: &TemplateContext, path: P) -> Result<String, human_errors::Error>;
+    fn load(path: &Path) -> Result<Metadata<'_>, human_errors::Error>;
 }
 
-pub fn render<P: AsRef<Path>>(
-    ctx: &TemplateContext,
-    path: P,
-) -> Option<Result<String, human_errors::Error>> {
+pub fn load(path: &Path) -> Option<Result<Metadata<'_>, human_errors::Error>> {
     let extension = path
-        .as_ref()
         .extension()
         .and_then(|e| e.to_str())
-        .unwrap_or_default();
-
-    if rawler::RawlerImage::supports(&extension.to_uppercase()) {
-        return Some(rawler::RawlerImage::render(ctx, path));
-    }
-
-    if rexif::RexifImage::supports(&extension.to_uppercase()) {
-        return Some(rexif::RexifImage::render(ctx, path));
-    }
+        .unwrap_or_default()
+        .to_uppercase();
+
+    let metadata = if rawler::RawlerImage::supports(&extension) {
+        rawler::RawlerImage::load(path)
+    } else if rexif::RexifImage::supports(&extension) {
+        rexif::RexifImage::load(path)
+    } else if heif::HeifImage::supports(&extension) {
+        heif::HeifImage::load(path)
+    } else {
+        return None;
+    };
+
+    // Google Takeout exports often strip the EXIF metadata entirely, in which case we rely
+    // on the JSON sidecar for everything.
+    let metadata = match (metadata, takeout::find_sidecar(path)) {
+        (Ok(metadata), Some(sidecar)) => takeout::apply(metadata, sidecar),
+        (Err(_), Some(sidecar)) => takeout::apply(Metadata::new(path), sidecar),
+        (metadata, None) => metadata,
+    };
+
+    Some(metadata.and_then(|metadata| metadata.validate().map(|_| metadata)))
+}
 
-    None
+/// Determines whether the provided file is a RAW image format.
+pub fn is_raw<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| rawler::RawlerImage::supports(&ext.to_uppercase()))
 }
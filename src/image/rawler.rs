@@ -3,8 +3,6 @@ use std::path::Path;
 use human_errors::ResultExt;
 use rawler::analyze::AnalyzerData;
 
-use crate::template;
-
 use super::{ImageLoader, Metadata};
 
 const SUPPORTED_FILE_TYPES: [&str; 27] = [
@@ -20,18 +18,15 @@ impl ImageLoader for RawlerImage {
         SUPPORTED_FILE_TYPES.contains(&extension)
     }
 
-    fn render<P: AsRef<Path>>(
-        ctx: &template::TemplateContext,
-        path: P,
-    ) -> Result<String, human_errors::Error> {
-        let img = rawler::analyze::analyze_metadata(path.as_ref()).wrap_err_as_system(
-            format!("Could not load image file '{}'.", path.as_ref().display()),
+    fn load(path: &Path) -> Result<Metadata<'_>, human_errors::Error> {
+        let img = rawler::analyze::analyze_metadata(path).wrap_system_err(
+            format!("Could not load image file '{}'.", path.display()),
             &["Make sure that you are attempting to load a valid image file format."],
         )?;
 
         match img.data {
             Some(AnalyzerData::Metadata(m)) => {
-                let mut metadata = Metadata::new(path.as_ref());
+                let mut metadata = Metadata::new(path);
 
                 metadata.artist = m.raw_metadata.exif.artist;
                 metadata.copyright = m.raw_metadata.exif.copyright;
@@ -45,15 +40,10 @@ impl ImageLoader for RawlerImage {
                 metadata.lens_make = m.raw_metadata.exif.lens_make;
                 metadata.lens_model = m.raw_metadata.exif.lens_model;
 
-                metadata.validate()?;
-
-                Ok(ctx.render(&metadata))
+                Ok(metadata)
             }
             _ => Err(human_errors::user(
-                format!(
-                    "Could not load image metadata from '{}'.",
-                    path.as_ref().display()
-                ),
+                format!("Could not load image metadata from '{}'.", path.display()),
                 &["Make sure that the image file contains the necessary metadata."],
             )),
         }
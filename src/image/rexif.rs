@@ -2,8 +2,6 @@ use std::path::Path;
 
 use human_errors::ResultExt;
 
-use crate::template;
-
 use super::{ImageLoader, Metadata};
 
 const SUPPORTED_FILE_TYPES: [&str; 6] = ["JPG", "JPEG", "TIF", "TIFF", "JIF", "JFIF"];
@@ -15,49 +13,70 @@ impl ImageLoader for RexifImage {
         SUPPORTED_FILE_TYPES.contains(&extension)
     }
 
-    fn render<P: AsRef<Path>>(
-        ctx: &template::TemplateContext,
-        path: P,
-    ) -> Result<String, human_errors::Error> {
-        let img = rexif::parse_file(path.as_ref()).wrap_err_as_system(
-            format!("Could not load image file '{}'.", path.as_ref().display()),
+    fn load(path: &Path) -> Result<Metadata<'_>, human_errors::Error> {
+        let data = std::fs::read(path).wrap_system_err(
+            format!("Could not load image file '{}'.", path.display()),
             &["Make sure that you are attempting to load a valid image file format."],
         )?;
 
-        let mut metadata = Metadata::new(path.as_ref());
+        let img = rexif::parse_buffer(&data).wrap_system_err(
+            format!("Could not load image file '{}'.", path.display()),
+            &["Make sure that you are attempting to load a valid image file format."],
+        )?;
 
-        for entry in img.entries {
-            match entry.tag {
-                rexif::ExifTag::Copyright => {
-                    metadata.copyright = entry.value.to_string().into();
-                }
-                rexif::ExifTag::UserComment => {
-                    metadata.artist = entry.value.to_string().into();
-                }
+        let mut metadata = metadata_from_exif(path, img);
+        if let Some(iptc) = super::iptc::parse_jpeg(&data) {
+            metadata.set_iptc(iptc);
+        }
 
-                rexif::ExifTag::DateTimeOriginal => {
-                    metadata.date_time = entry.value.to_string().into();
-                }
+        Ok(metadata)
+    }
+}
 
-                rexif::ExifTag::Make => {
-                    metadata.camera_make = entry.value.to_string().into();
-                }
-                rexif::ExifTag::Model => {
-                    metadata.camera_model = entry.value.to_string().into();
-                }
-                rexif::ExifTag::LensMake => {
-                    metadata.lens_make = entry.value.to_string().into();
-                }
-                rexif::ExifTag::LensModel => {
-                    metadata.lens_model = entry.value.to_string().into();
-                }
+/// Populates the image's [Metadata] from parsed EXIF data, which is shared by every
+/// loader which is able to locate an embedded EXIF block.
+pub(super) fn metadata_from_exif(path: &Path, img: rexif::ExifData) -> Metadata<'_> {
+    let mut metadata = Metadata::new(path);
 
-                _ => {}
+    for entry in img.entries {
+        match entry.tag {
+            rexif::ExifTag::Copyright => {
+                metadata.copyright = entry.value.to_string().into();
+            }
+            rexif::ExifTag::UserComment => {
+                metadata.artist = entry.value.to_string().into();
+            }
+
+            rexif::ExifTag::ImageDescription => {
+                metadata.description = entry.value.to_string().into();
+            }
+
+            rexif::ExifTag::DateTimeOriginal => {
+                metadata.date_time = entry.value.to_string().into();
+            }
+
+            rexif::ExifTag::Make => {
+                metadata.camera_make = entry.value.to_string().into();
+            }
+            rexif::ExifTag::Model => {
+                metadata.camera_model = entry.value.to_string().into();
+            }
+            rexif::ExifTag::LensMake => {
+                metadata.lens_make = entry.value.to_string().into();
+            }
+            rexif::ExifTag::LensModel => {
+                metadata.lens_model = entry.value.to_string().into();
             }
-        }
 
-        metadata.validate()?;
+            rexif::ExifTag::MakerNote => {
+                if let rexif::TagValue::Undefined(data, _) = &entry.value {
+                    metadata.content_identifier = super::apple::content_identifier(data);
+                }
+            }
 
-        Ok(ctx.render(&metadata))
+            _ => {}
+        }
     }
+
+    metadata
 }
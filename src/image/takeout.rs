@@ -0,0 +1,169 @@
+//! Support for the JSON sidecars which Google Takeout writes next to each exported photo,
+//! carrying the capture time, location and description which are often missing from its EXIF.
+
+use std::path::{Path, PathBuf};
+
+use human_errors::ResultExt;
+use serde::Deserialize;
+
+use super::Metadata;
+
+/// Google truncates the names of its JSON sidecars (excluding the `.json` extension) to this length.
+const MAX_SIDECAR_STEM_LENGTH: usize = 46;
+
+/// The suffixes which Google Photos appends to the names of edited copies of a photo.
+const EDITED_SUFFIXES: [&str; 1] = ["-edited"];
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Sidecar {
+    description: Option<String>,
+    photo_taken_time: Option<Timestamp>,
+    geo_data: Option<GeoData>,
+    geo_data_exif: Option<GeoData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Timestamp {
+    timestamp: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeoData {
+    latitude: f64,
+    longitude: f64,
+}
+
+/// Locates the Takeout JSON sidecar for a photo, accounting for the way in which Google
+/// truncates long names and numbers duplicates (`IMG_1234(1).JPG` → `IMG_1234.JPG(1).json`).
+pub fn find_sidecar<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    let path = path.as_ref();
+    let parent = path.parent()?;
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut stem = path.file_stem()?.to_string_lossy().to_string();
+
+    let mut counter = String::new();
+    if let Some(start) = stem.rfind('(').filter(|_| stem.ends_with(')')) {
+        if stem[start + 1..stem.len() - 1]
+            .chars()
+            .all(|c| c.is_ascii_digit())
+        {
+            counter = stem[start..].to_string();
+            stem.truncate(start);
+        }
+    }
+
+    let mut stems = vec![stem.clone()];
+    for suffix in EDITED_SUFFIXES {
+        if let Some(original) = stem.strip_suffix(suffix) {
+            stems.push(original.to_string());
+        }
+    }
+
+    stems
+        .iter()
+        .flat_map(|stem| {
+            [
+                format!("{}{}.supplemental-metadata", stem, extension),
+                format!("{}{}", stem, extension),
+                stem.to_string(),
+            ]
+        })
+        .flat_map(|name| [truncate(&name).to_string(), name])
+        .map(|name| parent.join(format!("{}{}.json", name, counter)))
+        .find(|candidate| candidate.is_file())
+}
+
+fn truncate(name: &str) -> &str {
+    match name.char_indices().nth(MAX_SIDECAR_STEM_LENGTH) {
+        Some((i, _)) => &name[..i],
+        None => name,
+    }
+}
+
+/// Fills in any metadata which is missing from the image using the values in its Takeout sidecar.
+pub fn apply<'a, P: AsRef<Path>>(
+    mut metadata: Metadata<'a>,
+    sidecar: P,
+) -> Result<Metadata<'a>, human_errors::Error> {
+    let content = std::fs::read_to_string(sidecar.as_ref()).wrap_user_err(
+        format!(
+            "Could not read the Google Takeout metadata file '{}'.",
+            sidecar.as_ref().display()
+        ),
+        &["Make sure that you have permission to read the file and try again."],
+    )?;
+
+    let sidecar: Sidecar = serde_json::from_str(&content).wrap_user_err(
+        format!(
+            "Could not parse the Google Takeout metadata file '{}'.",
+            sidecar.as_ref().display()
+        ),
+        &["Make sure that the file is a valid Google Takeout JSON metadata file."],
+    )?;
+
+    if metadata.date_time.is_none() {
+        metadata.date_time = sidecar
+            .photo_taken_time
+            .and_then(|t| t.timestamp.parse().ok())
+            .map(format_timestamp);
+    }
+
+    if metadata.description.is_none() {
+        metadata.description = sidecar.description.filter(|d| !d.trim().is_empty());
+    }
+
+    // Google reports unknown locations as 0.0, 0.0 so we treat those as missing.
+    if let Some(geo) = [sidecar.geo_data_exif, sidecar.geo_data]
+        .into_iter()
+        .flatten()
+        .find(|geo| geo.latitude != 0.0 || geo.longitude != 0.0)
+    {
+        metadata.latitude = metadata.latitude.or(Some(geo.latitude));
+        metadata.longitude = metadata.longitude.or(Some(geo.longitude));
+    }
+
+    Ok(metadata)
+}
+
+/// Formats a UNIX timestamp as an EXIF date/time (`YYYY:MM:DD HH:MM:SS`) in UTC.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86_400);
+    let seconds = timestamp.rem_euclid(86_400);
+
+    // Converts the number of days since the epoch into a civil date (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970:01:01 00:00:00");
+        assert_eq!(format_timestamp(1_577_836_800), "2020:01:01 00:00:00");
+        assert_eq!(format_timestamp(1_709_210_096), "2024:02:29 12:34:56");
+    }
+}
@@ -0,0 +1,287 @@
+//! Locates the encoded image data within JPEG and TIFF-based files (including most RAW formats),
+//! so that files which only differ in their metadata can be recognised as the same image.
+
+use std::{collections::HashSet, path::Path};
+
+use sha2::Digest;
+
+/// The TIFF tags which hold the offsets and byte counts of strips and tiles respectively.
+const STRIP_TAGS: (u16, u16) = (273, 279);
+const TILE_TAGS: (u16, u16) = (324, 325);
+const SUB_IFDS_TAG: u16 = 330;
+
+/// The maximum number of IFDs we will follow, which protects us from maliciously looped files.
+const MAX_IFDS: usize = 64;
+
+/// The hash of the encoded image data within a file, along with the number of bytes which
+/// hold everything else (metadata, thumbnails and container structure).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageData {
+    pub hash: String,
+    pub metadata_size: u64,
+}
+
+/// Hashes only the encoded image data within a JPEG or TIFF-based file, returning `None` if the
+/// file is in another format or its structure cannot be parsed.
+pub fn hash<P: AsRef<Path>>(path: P) -> std::io::Result<Option<ImageData>> {
+    let data = std::fs::read(path)?;
+
+    let Some(ranges) = jpeg_image_data(&data).or_else(|| tiff_image_data(&data)) else {
+        return Ok(None);
+    };
+
+    let mut digest = sha2::Sha256::new();
+    let mut image_size = 0;
+    for range in ranges {
+        digest.update(range);
+        image_size += range.len() as u64;
+    }
+
+    Ok(Some(ImageData {
+        hash: base16ct::lower::encode_string(&digest.finalize()),
+        // Strips which are referenced more than once are counted more than once.
+        metadata_size: (data.len() as u64).saturating_sub(image_size),
+    }))
+}
+
+/// Checks that a JPEG or TIFF-based file is structurally complete, in that a JPEG reaches its EOI
+/// marker and every IFD, strip and tile of a TIFF lies within the file. Files in other formats
+/// can't be checked, so they are always considered complete.
+pub fn is_complete<P: AsRef<Path>>(path: P) -> std::io::Result<bool> {
+    let data = std::fs::read(path)?;
+
+    Ok(if data.starts_with(&[0xFF, 0xD8]) {
+        jpeg_image_data(&data).is_some()
+    } else if TiffReader::new(&data).is_some() {
+        tiff_image_data(&data).is_some()
+    } else {
+        true
+    })
+}
+
+/// Finds every segment of a JPEG's primary image other than its APPn (EXIF, XMP, ICC, etc.) and
+/// COM segments, including the entropy-coded data which follows each scan header, up to its EOI marker.
+pub fn jpeg_image_data(data: &[u8]) -> Option<Vec<&[u8]>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    let mut position = 2;
+    loop {
+        // Markers may be preceded by any number of fill bytes.
+        while data.get(position) == Some(&0xFF) && data.get(position + 1) == Some(&0xFF) {
+            position += 1;
+        }
+
+        if *data.get(position)? != 0xFF {
+            return None;
+        }
+
+        let marker = *data.get(position + 1)?;
+        match marker {
+            0xD9 => {
+                ranges.push(&data[position..position + 2]);
+                return Some(ranges);
+            }
+            0x01 | 0xD0..=0xD7 => {
+                ranges.push(&data[position..position + 2]);
+                position += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes(data.get(position + 2..position + 4)?.try_into().ok()?);
+        let end = position + 2 + length as usize;
+        if length < 2 || end > data.len() {
+            return None;
+        }
+
+        if !matches!(marker, 0xE0..=0xEF | 0xFE) {
+            ranges.push(&data[position..end]);
+        }
+
+        position = end;
+
+        if marker == 0xDA {
+            // The entropy-coded data runs until the next marker which isn't a stuffed 0xFF byte or a restart marker.
+            let start = position;
+            loop {
+                match (data.get(position)?, data.get(position + 1)?) {
+                    (0xFF, 0x00 | 0xD0..=0xD7) => position += 2,
+                    (0xFF, _) => break,
+                    _ => position += 1,
+                }
+            }
+
+            ranges.push(&data[start..position]);
+        }
+    }
+}
+
+/// Finds the strips and tiles referenced by every IFD in a TIFF-based file (following both the
+/// IFD chain and any SubIFDs, which is where most RAW formats keep their full resolution data).
+pub fn tiff_image_data(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let reader = TiffReader::new(data)?;
+
+    let mut ranges = Vec::new();
+    let mut pending = vec![reader.u32(4)? as usize];
+    let mut visited = HashSet::new();
+
+    while let Some(offset) = pending.pop() {
+        if offset == 0 || !visited.insert(offset) {
+            continue;
+        }
+
+        if visited.len() > MAX_IFDS {
+            return None;
+        }
+
+        let count = reader.u16(offset)? as usize;
+        let mut tags = Vec::with_capacity(count);
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            tags.push((reader.u16(entry)?, reader.values(entry)?));
+        }
+
+        let find = |tag: u16| tags.iter().find(|(t, _)| *t == tag).map(|(_, v)| v);
+        for (offsets, counts) in [STRIP_TAGS, TILE_TAGS] {
+            if let (Some(offsets), Some(counts)) = (find(offsets), find(counts)) {
+                for (&start, &size) in offsets.iter().zip(counts.iter()) {
+                    let start = start as usize;
+                    ranges.push(data.get(start..start.checked_add(size as usize)?)?);
+                }
+            }
+        }
+
+        if let Some(sub_ifds) = find(SUB_IFDS_TAG) {
+            pending.extend(sub_ifds.iter().map(|&o| o as usize));
+        }
+
+        pending.push(reader.u32(offset + 2 + count * 12)? as usize);
+    }
+
+    if ranges.is_empty() {
+        return None;
+    }
+
+    Some(ranges)
+}
+
+struct TiffReader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+
+        Some(TiffReader {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    /// Reads the SHORT or LONG values of the IFD entry at `entry`, which are stored inline when they fit in 4 bytes.
+    fn values(&self, entry: usize) -> Option<Vec<u32>> {
+        let kind = self.u16(entry + 2)?;
+        let count = self.u32(entry + 4)? as usize;
+        let size = match kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Some(Vec::new()),
+        };
+
+        let start = match count.checked_mul(size)? {
+            0..=4 => entry + 8,
+            length if length <= self.data.len() => self.u32(entry + 8)? as usize,
+            _ => return None,
+        };
+
+        (0..count)
+            .map(|i| match size {
+                2 => self.u16(start + i * 2).map(u32::from),
+                _ => self.u32(start + i * 4),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCAN: &[u8] = &[
+        0xFF, 0xDA, 0x00, 0x04, 0x01, 0x02, 0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, 0xD9,
+    ];
+
+    #[test]
+    fn test_jpeg_ignores_metadata() {
+        let plain = [&[0xFF, 0xD8][..], SCAN].concat();
+        let tagged = [
+            &[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x05, b'E', b'x', b'i'][..],
+            &[0xFF, 0xFE, 0x00, 0x04, b'h', b'i'],
+            SCAN,
+        ]
+        .concat();
+
+        let plain_data = jpeg_image_data(&plain).unwrap();
+        assert_eq!(plain_data, jpeg_image_data(&tagged).unwrap());
+        assert_eq!(plain_data.concat(), SCAN);
+
+        assert_eq!(jpeg_image_data(&plain[..plain.len() - 2]), None);
+    }
+
+    #[test]
+    fn test_tiff_strips() {
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend([2, 0]);
+        tiff.extend([0x11, 0x01, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+        tiff.extend([0x17, 0x01, 3, 0, 1, 0, 0, 0, 4, 0, 0, 0]);
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(b"data");
+
+        assert_eq!(tiff_image_data(&tiff).unwrap(), vec![b"data"]);
+        assert_eq!(tiff_image_data(&tiff[..40]), None);
+    }
+
+    #[test]
+    fn test_tiff_duplicate_strip_offsets() {
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend([2, 0]);
+        tiff.extend([0x11, 0x01, 4, 0, 8, 0, 0, 0, 38, 0, 0, 0]);
+        tiff.extend([0x17, 0x01, 4, 0, 8, 0, 0, 0, 70, 0, 0, 0]);
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend([38, 0, 0, 0].repeat(8));
+        tiff.extend([34, 0, 0, 0].repeat(8));
+        assert_eq!(tiff.len(), 102);
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("image.tif");
+        std::fs::write(&path, &tiff).unwrap();
+
+        assert_eq!(hash(&path).unwrap().unwrap().metadata_size, 0);
+    }
+}
@@ -0,0 +1,186 @@
+//! A persistent ledger of the content hashes of every file which has ever been imported into the
+//! target library, which allows photos that were deliberately deleted from the library to be
+//! recognised when the memory card they came from is imported again.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::store::{format_path, parse_path, Store};
+
+const LEDGER_FILE: &str = "imported";
+
+/// The record of a file having been imported into the library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// When the file was imported, in seconds since the Unix epoch.
+    pub imported: u64,
+    /// Where the file was imported to, relative to the library.
+    pub path: PathBuf,
+}
+
+pub struct ImportLedger {
+    root: PathBuf,
+    store: Store,
+    entries: HashMap<String, Entry>,
+    forgotten: HashMap<String, Entry>,
+}
+
+impl ImportLedger {
+    /// Opens the ledger for the library at `root`.
+    pub fn open<P: AsRef<Path>>(root: P) -> std::io::Result<Self> {
+        let store = Store::new(&root, LEDGER_FILE);
+        let entries = store
+            .load(3)?
+            .iter()
+            .filter_map(|r| parse_entry(r))
+            .collect();
+
+        Ok(ImportLedger {
+            root: root.as_ref().to_path_buf(),
+            store,
+            entries,
+            forgotten: HashMap::new(),
+        })
+    }
+
+    /// Finds the record of a file with the given content hash having been imported.
+    pub fn get(&self, hash: &str) -> Option<&Entry> {
+        self.entries.get(hash)
+    }
+
+    /// Records that a file with the given content hash has just been imported to `path`,
+    /// persisting it immediately.
+    pub fn record<P: AsRef<Path>>(&mut self, hash: &str, path: P) -> std::io::Result<()> {
+        let entry = self.entry(path.as_ref());
+
+        let record = format_entry(hash, &entry);
+        self.store
+            .append(&record.iter().map(String::as_str).collect::<Vec<_>>())?;
+
+        self.entries.insert(hash.to_string(), entry);
+        Ok(())
+    }
+
+    /// Records that an audit would have imported a file with the given content hash to `path`,
+    /// without persisting it.
+    pub fn simulate_record<P: AsRef<Path>>(&mut self, hash: &str, path: P) {
+        let entry = self.entry(path.as_ref());
+        self.entries.insert(hash.to_string(), entry);
+    }
+
+    /// Removes every entry for which `predicate` returns true, returning the removed entries.
+    /// The ledger is only rewritten once [ImportLedger::save] is called.
+    pub fn forget<F: Fn(&str, &Entry) -> bool>(&mut self, predicate: F) -> Vec<(String, Entry)> {
+        let hashes: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(hash, entry)| predicate(hash, entry))
+            .map(|(hash, _)| hash.clone())
+            .collect();
+
+        let mut forgotten: Vec<(String, Entry)> = hashes
+            .into_iter()
+            .filter_map(|hash| self.entries.remove_entry(&hash))
+            .collect();
+        forgotten.sort_by(|(_, a), (_, b)| a.path.cmp(&b.path));
+        self.forgotten.extend(forgotten.iter().cloned());
+        forgotten
+    }
+
+    /// Rewrites the ledger without the entries which were forgotten, replacing the old ledger
+    /// atomically. Files which other processes recorded since the ledger was opened are kept.
+    pub fn save(&self) -> std::io::Result<()> {
+        self.store.update(3, |records| {
+            let entries: HashMap<String, Entry> =
+                records.iter().filter_map(|r| parse_entry(r)).collect();
+
+            Ok(entries
+                .into_iter()
+                .filter(|(hash, entry)| self.forgotten.get(hash) != Some(entry))
+                .map(|(hash, entry)| format_entry(&hash, &entry))
+                .collect())
+        })
+    }
+
+    fn entry(&self, path: &Path) -> Entry {
+        Entry {
+            imported: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            path: path.strip_prefix(&self.root).unwrap_or(path).to_path_buf(),
+        }
+    }
+}
+
+fn format_entry(hash: &str, entry: &Entry) -> Vec<String> {
+    vec![
+        hash.to_string(),
+        entry.imported.to_string(),
+        format_path(&entry.path),
+    ]
+}
+
+fn parse_entry(record: &[String]) -> Option<(String, Entry)> {
+    let [hash, imported, path] = record else {
+        return None;
+    };
+
+    Some((
+        hash.clone(),
+        Entry {
+            imported: imported.parse().ok()?,
+            path: parse_path(path),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_and_forget() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let mut ledger = ImportLedger::open(dir).unwrap();
+        ledger.record("aaaa", dir.join("2024/a.jpg")).unwrap();
+        ledger.record("bbbb", dir.join("2024/b.jpg")).unwrap();
+
+        let mut ledger = ImportLedger::open(dir).unwrap();
+        assert_eq!(
+            ledger.get("aaaa").unwrap().path,
+            PathBuf::from("2024/a.jpg")
+        );
+
+        let forgotten = ledger.forget(|hash, _| hash == "aaaa");
+        assert_eq!(forgotten.len(), 1);
+        ledger.save().unwrap();
+
+        let ledger = ImportLedger::open(dir).unwrap();
+        assert!(ledger.get("aaaa").is_none());
+        assert!(ledger.get("bbbb").is_some());
+    }
+
+    #[test]
+    fn test_save_keeps_concurrent_records() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let mut ledger = ImportLedger::open(dir).unwrap();
+        ledger.record("aaaa", dir.join("2024/a.jpg")).unwrap();
+
+        let mut forgetting = ImportLedger::open(dir).unwrap();
+        ledger.record("bbbb", dir.join("2024/b.jpg")).unwrap();
+        forgetting.forget(|hash, _| hash == "aaaa");
+        forgetting.save().unwrap();
+
+        let ledger = ImportLedger::open(dir).unwrap();
+        assert!(ledger.get("aaaa").is_none());
+        assert!(ledger.get("bbbb").is_some());
+    }
+}
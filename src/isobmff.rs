@@ -0,0 +1,166 @@
+//! A minimal reader for the ISO base media file format (and its QuickTime ancestor),
+//! which is the container used by HEIF images, MOV and MP4 videos.
+
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// The largest top-level atom we are willing to load into memory when looking for metadata.
+const MAX_ATOM_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A single atom (or "box") within an ISOBMFF container.
+#[derive(Debug, Clone, Copy)]
+pub struct Atom<'a> {
+    pub kind: [u8; 4],
+    pub data: &'a [u8],
+}
+
+impl<'a> Atom<'a> {
+    /// The children of this atom, assuming that it is a container atom.
+    pub fn children(&self) -> Atoms<'a> {
+        atoms(self.data)
+    }
+
+    /// The children of this atom, skipping the version and flags header used by "full" atoms.
+    pub fn full_children(&self) -> Atoms<'a> {
+        atoms(self.data.get(4..).unwrap_or_default())
+    }
+
+    pub fn find(&self, kind: &[u8; 4]) -> Option<Atom<'a>> {
+        self.children().find(|atom| &atom.kind == kind)
+    }
+}
+
+pub struct Atoms<'a> {
+    data: &'a [u8],
+}
+
+/// Iterates over the sequence of atoms contained in the provided buffer.
+pub fn atoms(data: &[u8]) -> Atoms<'_> {
+    Atoms { data }
+}
+
+impl<'a> Iterator for Atoms<'a> {
+    type Item = Atom<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = read_u32(self.data, 0)? as u64;
+        let kind: [u8; 4] = self.data.get(4..8)?.try_into().ok()?;
+
+        let (header, size) = match size {
+            0 => (8, self.data.len() as u64),
+            1 => (16, read_u64(self.data, 8)?),
+            size => (8, size),
+        };
+
+        if size < header || size > self.data.len() as u64 {
+            self.data = &[];
+            return None;
+        }
+
+        let data = &self.data[header as usize..size as usize];
+        self.data = &self.data[size as usize..];
+
+        Some(Atom { kind, data })
+    }
+}
+
+/// Reads the first top-level atom of the given kind from a file, seeking past any others
+/// so that large media payloads never need to be loaded into memory.
+pub fn read_top_level<P: AsRef<Path>>(path: P, kind: &[u8; 4]) -> std::io::Result<Option<Vec<u8>>> {
+    let mut file = std::fs::File::open(path)?;
+    let length = file.metadata()?.len();
+    let mut position = 0;
+
+    while length.saturating_sub(position) >= 8 {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header[..8])?;
+
+        let (header_size, size) = match read_u32(&header, 0).unwrap_or_default() as u64 {
+            0 => (8, length - position),
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                (16, read_u64(&header, 8).unwrap_or_default())
+            }
+            size => (8, size),
+        };
+
+        if size < header_size {
+            break;
+        }
+
+        if &header[4..8] == kind {
+            if size > MAX_ATOM_SIZE {
+                return Ok(None);
+            }
+
+            let mut data = vec![0u8; (size - header_size) as usize];
+            file.read_exact(&mut data)?;
+            return Ok(Some(data));
+        }
+
+        // A malformed length could otherwise wrap around and send us back to the start of the file.
+        let Some(next) = position.checked_add(size) else {
+            break;
+        };
+        position = next;
+    }
+
+    Ok(None)
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Reads a big-endian unsigned integer of 0, 4 or 8 bytes, as used by the `iloc` atom.
+pub fn read_uint(data: &[u8], offset: usize, size: usize) -> Option<u64> {
+    match size {
+        0 => Some(0),
+        4 => read_u32(data, offset).map(|v| v as u64),
+        8 => read_u64(data, offset),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_top_level() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("video.mov");
+
+        let mut data = [&[0, 0, 0, 12][..], b"ftyp", b"qt  "].concat();
+        data.extend([&[0, 0, 0, 12][..], b"moov", b"data"].concat());
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(
+            read_top_level(&path, b"moov").unwrap(),
+            Some(b"data".to_vec())
+        );
+        assert_eq!(read_top_level(&path, b"meta").unwrap(), None);
+
+        // An atom whose 64-bit length runs past the end of the address space is skipped safely.
+        let mut data = [&[0, 0, 0, 1][..], b"free"].concat();
+        data.extend(u64::MAX.to_be_bytes());
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(read_top_level(&path, b"moov").unwrap(), None);
+    }
+}
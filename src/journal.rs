@@ -0,0 +1,393 @@
+//! A durable journal of every change made to the filesystem during a run, which allows the run
+//! to be reversed with `imgsort undo`. Files which would otherwise be removed during the run are
+//! kept alongside the journal so that they can be restored.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{conflict_manager, image, store::STATE_DIR};
+
+const JOURNAL_DIR: &str = "journal";
+const JOURNAL_EXTENSION: &str = "jsonl";
+
+/// A single change made to the filesystem.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Action {
+    /// A directory was created.
+    Mkdir { path: PathBuf },
+    /// A file was moved from `source` to `target`.
+    Move {
+        source: PathBuf,
+        target: PathBuf,
+        hash: String,
+    },
+    /// A file was created at `target` (by copying, linking or extracting it from `source`).
+    Create {
+        source: PathBuf,
+        target: PathBuf,
+        hash: String,
+    },
+    /// A file was removed from `path`, and has been kept at `kept` so that it can be restored.
+    Remove {
+        path: PathBuf,
+        kept: PathBuf,
+        hash: String,
+    },
+}
+
+pub struct Journal {
+    run_id: String,
+    path: PathBuf,
+    kept: PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: std::fs::File,
+    entries: usize,
+}
+
+impl Journal {
+    /// Starts the journal for a new run within the library at `root`, named after the current time.
+    pub fn begin<P: AsRef<Path>>(root: P) -> std::io::Result<Self> {
+        let directory = journal_dir(root.as_ref());
+        std::fs::create_dir_all(&directory)?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let timestamp = image::format_timestamp(now as i64)
+            .replace(':', "")
+            .replace(' ', "-");
+
+        let mut n = 0;
+        loop {
+            let run_id = match n {
+                0 => timestamp.clone(),
+                n => format!("{}.{}", timestamp, n),
+            };
+
+            let path = directory.join(format!("{}.{}", run_id, JOURNAL_EXTENSION));
+            match std::fs::OpenOptions::new()
+                .append(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => {
+                    return Ok(Journal {
+                        kept: directory.join(&run_id),
+                        run_id,
+                        path,
+                        state: Mutex::new(State { file, entries: 0 }),
+                    })
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The identifier of this run, which is passed to `imgsort undo`.
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Appends an action to the journal, only returning once it has been written to disk.
+    pub fn record(&self, action: &Action) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        writeln!(state.file, "{}", serde_json::to_string(action)?)?;
+        state.file.sync_data()?;
+        state.entries += 1;
+        Ok(())
+    }
+
+    /// Creates a directory and any missing parents, recording each directory which was created.
+    pub fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        let mut missing: Vec<&Path> = path.ancestors().take_while(|p| !p.exists()).collect();
+        std::fs::create_dir_all(path)?;
+
+        missing.reverse();
+        for path in missing {
+            self.record(&Action::Mkdir {
+                path: path.to_path_buf(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves a file which would otherwise be removed into this run's directory, recording it so
+    /// that it can be restored.
+    pub fn keep(&self, path: &Path, hash: String) -> std::io::Result<PathBuf> {
+        let n = self.state.lock().unwrap().entries;
+        let kept = self
+            .kept
+            .join(n.to_string())
+            .join(path.file_name().unwrap_or_default());
+
+        std::fs::create_dir_all(kept.parent().unwrap())?;
+        conflict_manager::move_file(path, &kept, false)?;
+        self.record(&Action::Remove {
+            path: path.to_path_buf(),
+            kept: kept.clone(),
+            hash,
+        })?;
+
+        Ok(kept)
+    }
+
+    /// Completes the run, discarding the journal if nothing was changed. Returns whether the
+    /// journal was kept.
+    pub fn finish(self) -> std::io::Result<bool> {
+        if self.state.into_inner().unwrap().entries == 0 {
+            std::fs::remove_file(&self.path)?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
+fn journal_dir(root: &Path) -> PathBuf {
+    root.join(STATE_DIR).join(JOURNAL_DIR)
+}
+
+/// The identifiers of every run which has a journal in the library at `root`, oldest first.
+pub fn runs<P: AsRef<Path>>(root: P) -> std::io::Result<Vec<String>> {
+    let entries = match std::fs::read_dir(journal_dir(root.as_ref())) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut runs = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == JOURNAL_EXTENSION) {
+            if let Some(run_id) = path.file_stem() {
+                runs.push(run_id.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    runs.sort();
+    Ok(runs)
+}
+
+/// Reads the actions recorded for a run, in the order in which they happened.
+pub fn load<P: AsRef<Path>>(root: P, run_id: &str) -> std::io::Result<Vec<Action>> {
+    let path = journal_dir(root.as_ref()).join(format!("{}.{}", run_id, JOURNAL_EXTENSION));
+    let file = std::fs::File::open(path)?;
+
+    let mut actions = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        // A run which was interrupted may have left a partially written final line behind.
+        if let Ok(action) = serde_json::from_str(&line) {
+            actions.push(action);
+        }
+    }
+
+    Ok(actions)
+}
+
+/// Checks that a run can be undone, returning a description of every file which has changed
+/// (or been replaced) since the run, which would make undoing it unsafe.
+pub fn check(actions: &[Action]) -> Vec<String> {
+    // Undoing earlier actions relies on later ones having been undone, so we track the files
+    // which would be created or removed along the way.
+    let mut overlay: HashMap<&Path, bool> = HashMap::new();
+    let exists = |overlay: &HashMap<&Path, bool>, path: &Path| {
+        overlay.get(path).copied().unwrap_or_else(|| path.exists())
+    };
+    let unchanged = |overlay: &HashMap<&Path, bool>, path: &Path, hash: &str| {
+        overlay.contains_key(path)
+            || conflict_manager::file_hash(path).is_ok_and(|current| current == hash)
+    };
+
+    let mut problems = Vec::new();
+    for action in actions.iter().rev() {
+        match action {
+            Action::Mkdir { .. } => {}
+            Action::Move {
+                source,
+                target,
+                hash,
+            } => {
+                if !exists(&overlay, target) {
+                    problems.push(format!("'{}' no longer exists", target.display()));
+                } else if !unchanged(&overlay, target, hash) {
+                    problems.push(format!("'{}' has been modified", target.display()));
+                } else if exists(&overlay, source) {
+                    problems.push(format!("'{}' already exists", source.display()));
+                }
+
+                overlay.insert(target, false);
+                overlay.insert(source, true);
+            }
+            Action::Create { target, hash, .. } => {
+                if exists(&overlay, target) && !unchanged(&overlay, target, hash) {
+                    problems.push(format!("'{}' has been modified", target.display()));
+                }
+
+                overlay.insert(target, false);
+            }
+            Action::Remove { path, kept, .. } => {
+                if !kept.exists() {
+                    problems.push(format!("'{}' no longer exists", kept.display()));
+                } else if exists(&overlay, path) {
+                    problems.push(format!("'{}' already exists", path.display()));
+                }
+
+                overlay.insert(path, true);
+            }
+        }
+    }
+
+    problems
+}
+
+/// Reverses every action in a run (which must have been [check]ed first), newest first, and then
+/// discards its journal.
+pub fn undo<P: AsRef<Path>>(
+    root: P,
+    run_id: &str,
+    actions: &[Action],
+    audit: bool,
+) -> std::io::Result<()> {
+    for action in actions.iter().rev() {
+        match action {
+            Action::Mkdir { path } => {
+                info!("rmdir '{}'", path.display());
+                // Directories which other files have since been added to are left alone.
+                if !audit {
+                    let _ = std::fs::remove_dir(path);
+                }
+            }
+            Action::Move { source, target, .. } => {
+                info!("mv '{}' '{}'", target.display(), source.display());
+                if !audit {
+                    std::fs::create_dir_all(source.parent().unwrap())?;
+                    conflict_manager::move_file(target, source, false)?;
+                }
+            }
+            Action::Create { target, .. } => {
+                info!("rm '{}'", target.display());
+                if !audit {
+                    match std::fs::remove_file(target) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                        _ => {}
+                    }
+                }
+            }
+            Action::Remove { path, kept, .. } => {
+                info!("mv '{}' '{}'", kept.display(), path.display());
+                if !audit {
+                    std::fs::create_dir_all(path.parent().unwrap())?;
+                    conflict_manager::move_file(kept, path, false)?;
+                }
+            }
+        }
+    }
+
+    if !audit {
+        discard(root.as_ref(), run_id)?;
+    }
+
+    Ok(())
+}
+
+/// Discards the journals (and kept files) of every run which finished more than `older_than` ago,
+/// after which they can no longer be undone. Returns the runs which were (or would be) discarded.
+pub fn prune<P: AsRef<Path>>(
+    root: P,
+    older_than: Duration,
+    audit: bool,
+) -> std::io::Result<Vec<String>> {
+    let root = root.as_ref();
+    let cutoff = SystemTime::now() - older_than;
+
+    let mut pruned = Vec::new();
+    for run_id in runs(root)? {
+        let path = journal_dir(root).join(format!("{}.{}", run_id, JOURNAL_EXTENSION));
+        if std::fs::metadata(&path)?.modified()? > cutoff {
+            continue;
+        }
+
+        info!("rm '{}'", path.display());
+        if !audit {
+            discard(root, &run_id)?;
+        }
+
+        pruned.push(run_id);
+    }
+
+    Ok(pruned)
+}
+
+fn discard(root: &Path, run_id: &str) -> std::io::Result<()> {
+    let directory = journal_dir(root);
+    match std::fs::remove_dir_all(directory.join(run_id)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    std::fs::remove_file(directory.join(format!("{}.{}", run_id, JOURNAL_EXTENSION)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_undo() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("source")).unwrap();
+        std::fs::write(dir.join("source/a.jpg"), "a").unwrap();
+        std::fs::write(dir.join("source/b.jpg"), "b").unwrap();
+
+        let library = dir.join("library");
+        let journal = Journal::begin(&library).unwrap();
+        let run_id = journal.run_id().to_string();
+        journal.create_dir_all(&library.join("2024/05")).unwrap();
+        std::fs::rename(dir.join("source/a.jpg"), library.join("2024/05/a.jpg")).unwrap();
+        journal
+            .record(&Action::Move {
+                source: dir.join("source/a.jpg"),
+                target: library.join("2024/05/a.jpg"),
+                hash: conflict_manager::file_hash(library.join("2024/05/a.jpg")).unwrap(),
+            })
+            .unwrap();
+        journal
+            .keep(&dir.join("source/b.jpg"), "unused".to_string())
+            .unwrap();
+        journal.finish().unwrap();
+        assert!(!dir.join("source/b.jpg").exists());
+
+        assert_eq!(runs(&library).unwrap(), vec![run_id.clone()]);
+        let actions = load(&library, &run_id).unwrap();
+        assert_eq!(actions.len(), 4);
+
+        std::fs::write(library.join("2024/05/a.jpg"), "edited").unwrap();
+        assert_eq!(check(&actions).len(), 1);
+        std::fs::write(library.join("2024/05/a.jpg"), "a").unwrap();
+        assert!(check(&actions).is_empty());
+
+        undo(&library, &run_id, &actions, false).unwrap();
+        assert!(dir.join("source/a.jpg").exists());
+        assert!(dir.join("source/b.jpg").exists());
+        assert!(!library.join("2024").exists());
+        assert!(runs(&library).unwrap().is_empty());
+    }
+}
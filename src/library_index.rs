@@ -0,0 +1,163 @@
+//! A persistent index of the content hashes of every file in the target library, which allows
+//! duplicates to be detected even when they would be imported under a different name.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{file_index::FileIndex, hash_cache::HashCache};
+
+const INDEX_FILE: &str = "index";
+
+pub struct LibraryIndex {
+    files: FileIndex<String>,
+    by_hash: HashMap<String, Vec<PathBuf>>,
+}
+
+impl LibraryIndex {
+    /// Opens the index for the library at `root`, bringing it up to date with the files on disk.
+    ///
+    /// Only files which have been added or modified since the index was last updated are hashed
+    /// (on a pool of `threads` workers), and files for which `ignore` returns true are excluded from
+    /// the index entirely. The refreshed index is only persisted once [LibraryIndex::save] is called.
+    pub fn open<P: AsRef<Path>, F: Fn(&Path) -> bool>(
+        root: P,
+        cache: &HashCache,
+        threads: usize,
+        ignore: F,
+    ) -> std::io::Result<Self> {
+        let files = FileIndex::open(root, INDEX_FILE, threads, ignore, |path| {
+            cache.hash(path).ok()
+        })?;
+
+        let mut index = LibraryIndex {
+            files,
+            by_hash: HashMap::new(),
+        };
+
+        let entries: Vec<(PathBuf, String)> = index
+            .files
+            .iter()
+            .map(|(path, hash)| (path.to_path_buf(), hash.clone()))
+            .collect();
+
+        for (path, hash) in entries {
+            index.add(path, hash);
+        }
+
+        Ok(index)
+    }
+
+    /// Finds a file in the library with the provided content hash, ignoring the file at `exclude`
+    /// and any copies for which `exists` returns false (since they may have been removed since
+    /// they were indexed).
+    pub fn find<P, F>(&self, hash: &str, exclude: P, exists: F) -> Option<PathBuf>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path) -> bool,
+    {
+        self.by_hash
+            .get(hash)?
+            .iter()
+            .map(|path| self.files.root().join(path))
+            .find(|path| path != exclude.as_ref() && exists(path))
+    }
+
+    /// Records a file which has been added to the library, persisting it immediately.
+    pub fn insert<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        hash: Option<String>,
+        cache: &HashCache,
+    ) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let previous = path
+            .strip_prefix(self.files.root())
+            .ok()
+            .and_then(|relative| self.files.get(relative).cloned());
+        let inserted = self.files.insert(path, || match hash {
+            Some(hash) => Ok(hash),
+            None => cache.hash(path),
+        })?;
+
+        if let Some(relative) = inserted {
+            if let Some(previous) = previous {
+                self.remove(&relative, &previous);
+            }
+
+            let hash = self.files.get(&relative).cloned().unwrap_or_default();
+            self.add(relative, hash);
+        }
+
+        Ok(())
+    }
+
+    /// Records a file which an audit would have added to the library, without persisting it
+    /// (or reading the file, which doesn't exist yet).
+    pub fn simulate_insert<P: AsRef<Path>>(&mut self, path: P, hash: String) {
+        let previous = path
+            .as_ref()
+            .strip_prefix(self.files.root())
+            .ok()
+            .and_then(|relative| self.files.get(relative).cloned());
+
+        if let Some(relative) = self.files.simulate_insert(path, hash.clone()) {
+            if let Some(previous) = previous {
+                self.remove(&relative, &previous);
+            }
+
+            self.add(relative, hash);
+        }
+    }
+
+    /// Adds a copy of a file with the given hash, keeping the copies in order so that the same one
+    /// is always found first.
+    fn add(&mut self, path: PathBuf, hash: String) {
+        let paths = self.by_hash.entry(hash).or_default();
+        if let Err(i) = paths.binary_search(&path) {
+            paths.insert(i, path);
+        }
+    }
+
+    /// Removes a copy of a file which has been replaced with different content.
+    fn remove(&mut self, path: &Path, hash: &str) {
+        if let Some(paths) = self.by_hash.get_mut(hash) {
+            paths.retain(|p| p != path);
+            if paths.is_empty() {
+                self.by_hash.remove(hash);
+            }
+        }
+    }
+
+    /// Persists the refreshed index, dropping any files which have been removed from the library.
+    pub fn save(&self) -> std::io::Result<()> {
+        self.files.save()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_other_copies() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("a.jpg"), "image").unwrap();
+        std::fs::write(dir.join("b.jpg"), "image").unwrap();
+
+        let cache = HashCache::disabled();
+        let mut index = LibraryIndex::open(dir, &cache, 1, |_| false).unwrap();
+        let hash = cache.hash(dir.join("a.jpg")).unwrap();
+        let exists = |path: &Path| path.is_file();
+        assert_eq!(index.find(&hash, "", exists), Some(dir.join("a.jpg")));
+
+        std::fs::remove_file(dir.join("a.jpg")).unwrap();
+        assert_eq!(index.find(&hash, "", exists), Some(dir.join("b.jpg")));
+
+        std::fs::write(dir.join("b.jpg"), "edited").unwrap();
+        index.insert(dir.join("b.jpg"), None, &cache).unwrap();
+        assert_eq!(index.find(&hash, "", exists), None);
+    }
+}
@@ -1,22 +1,105 @@
 use log::{error, info, warn};
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use human_errors::ResultExt;
 
 mod config;
 mod conflict_manager;
+mod dedupe;
 mod errors;
+mod file_group;
+mod file_index;
+mod hash_cache;
 mod image;
+mod image_data;
+mod import_ledger;
+mod isobmff;
+mod journal;
+mod library_index;
+mod motion_photos;
+mod overlay;
+mod pairs;
+mod perceptual;
+mod pipeline;
+mod plan;
+mod quarantine;
+mod settle;
+mod sidecars;
+mod store;
 mod template;
+mod trash;
+#[cfg(target_os = "linux")]
+mod watch;
 
 #[derive(Parser)]
 struct Args {
-    #[arg(short, long, default_value = "config.toml")]
+    #[arg(short, long, default_value = "config.toml", global = true)]
     config: PathBuf,
 
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     audit: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Finds files with identical content within a library and keeps only one copy of each.
+    Dedupe {
+        /// The directory to scan for duplicates, which defaults to your target directory.
+        directory: Option<PathBuf>,
+
+        /// Which copy of each duplicated file is kept, overriding `dedupe.keep`.
+        #[arg(long)]
+        keep: Option<config::DedupeKeep>,
+
+        /// What happens to the other copies, overriding `dedupe.action`.
+        #[arg(long)]
+        action: Option<config::DedupeAction>,
+    },
+
+    /// Removes files from the import ledger so that they will be imported again, where each entry
+    /// is a file (matched by its content), a path within the library or a content hash.
+    Forget {
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+
+    /// Plans an import without making any changes, writing every change it would make to a file
+    /// which can be reviewed (or edited) and then applied later.
+    Plan {
+        /// The file to which the plan is written.
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Applies a plan created by `imgsort plan`, skipping any images whose files have changed since.
+    Apply {
+        /// The plan to apply.
+        plan: PathBuf,
+    },
+
+    /// Reverses a previous run, moving every file back to where it came from and restoring any
+    /// files which were removed.
+    Undo {
+        /// The run to undo, which defaults to the most recent run which has not been undone.
+        run_id: Option<String>,
+    },
+
+    /// Watches the source directory and imports new images as soon as they have finished being written.
+    Watch,
+
+    /// Removes quarantined images which have been kept for longer than the retention period.
+    Purge {
+        /// The number of days for which quarantined images are kept, overriding `quarantine.retention_days`.
+        #[arg(long)]
+        older_than: Option<u64>,
+    },
 }
 
 fn main() {
@@ -34,78 +117,1625 @@ fn main() {
 }
 
 fn run(args: Args) -> Result<(), errors::Error> {
-    let config = config::Config::load(args.config)?;
-    let template = template::TemplateContext::new(&config.template)
-        .with_transform("lowercase", template::transform(|s| s.to_lowercase()))
-        .with_transform("uppercase", template::transform(|s| s.to_uppercase()))
-        .with_transform(
-            "path_safe",
-            template::transform(|s| s.replace(['/', '\\', ':', ';', '#'], "")),
-        )
-        .with_transform("trim", template::transform(|s| s.trim().to_owned()));
+    let config = config::Config::load(&args.config)?;
+    let quarantine =
+        quarantine::Quarantine::new(&config, template_context(&config.quarantine.template));
 
-    let mut written_files = HashSet::new();
+    match args.command {
+        Some(Command::Dedupe {
+            directory,
+            keep,
+            action,
+        }) => dedupe(
+            &config,
+            &quarantine,
+            directory.as_deref().unwrap_or(&config.target),
+            keep.unwrap_or(config.dedupe.keep),
+            action.unwrap_or(config.dedupe.action),
+            args.audit,
+        ),
+        Some(Command::Forget { entries }) => forget(&config, &entries, args.audit),
+        Some(Command::Plan { out }) => {
+            // Plans are always simulated, since they are only applied once they have been reviewed.
+            let context = Context::open(&config, true)?;
+            let mut indexes = Indexes::open(&config, &quarantine, &context)?;
+            let mut plan = plan::Plan::default();
+            let files = source_files(&config);
+            import_images(
+                &config,
+                &quarantine,
+                &context,
+                &mut indexes,
+                Some(&mut plan),
+                files,
+            )?;
+            plan.save(&out).wrap_user_err(
+                format!("Unable to write the plan to '{}'.", out.display()),
+                &["Make sure that you have permission to write to this file and try again."],
+            )?;
 
-    for entry in walkdir::WalkDir::new(&config.source)
+            info!(
+                "Planned {} imports in '{}'.",
+                plan.imports.len(),
+                out.display()
+            );
+            Ok(())
+        }
+        Some(Command::Apply { plan }) => apply_plan(&config, &quarantine, &plan, args.audit),
+        Some(Command::Undo { run_id }) => undo(&config, run_id, args.audit),
+        Some(Command::Purge { older_than }) => purge(&config, &quarantine, older_than, args.audit),
+        Some(Command::Watch) => watch(&config, &quarantine, args.audit),
+        None => {
+            let context = Context::open(&config, args.audit)?;
+            let mut indexes = Indexes::open(&config, &quarantine, &context)?;
+            import_images(
+                &config,
+                &quarantine,
+                &context,
+                &mut indexes,
+                None,
+                source_files(&config),
+            )
+        }
+    }
+}
+
+/// Finds every file in the source directory which could be imported.
+fn source_files(config: &config::Config) -> Vec<PathBuf> {
+    // Files are visited in a stable order so that conflicts between them are always resolved the same way.
+    walkdir::WalkDir::new(&config.source)
+        .sort_by_file_name()
         .into_iter()
         .filter_map(|entry| entry.ok())
         .filter(|entry| entry.file_type().is_file())
-    {
-        if config.synology
-            && entry
-                .path()
-                .components()
-                .any(|p| p.as_os_str().to_string_lossy() == "@eaDir")
-        {
-            continue;
-        }
+        .map(|entry| entry.into_path())
+        .filter(|path| is_importable(config, path))
+        .collect()
+}
 
-        if written_files.contains(entry.path()) {
-            continue;
-        }
+/// Whether a file could be imported, rather than being a Synology index, a sidecar (which is
+/// imported alongside its image) or an upload which is still in progress.
+fn is_importable(config: &config::Config, path: &Path) -> bool {
+    (!config.synology || !is_synology_index(path))
+        && !sidecars::is_sidecar(path, &config.sidecars.extensions)
+        && !settle::is_temporary_upload(path)
+}
 
-        match image::render(&template, entry.path()) {
-            Some(Ok(target)) => {
-                let mut target = config.target.join(target);
+/// Imports the provided files from the source directory, keeping the `indexes` of the library up
+/// to date, or adds what would be done to `planned` (which requires an audit `context`).
+fn import_images(
+    config: &config::Config,
+    quarantine: &quarantine::Quarantine,
+    context: &Context,
+    indexes: &mut Indexes,
+    mut planned: Option<&mut plan::Plan>,
+    mut files: Vec<PathBuf>,
+) -> Result<(), errors::Error> {
+    let template = template_context(&config.template);
+    let audit = context.is_audit();
+    let hashes = &context.hashes;
 
-                if let Some(ext) = entry.path().extension() {
-                    target = target.with_extension(ext);
+    // RAW images are imported first so that they can claim the JPEGs they were shot alongside.
+    files.sort_by_key(|path| !image::is_raw(path));
+
+    let journal = context.begin_journal(config)?;
+    let conflicts = context.conflicts(config, quarantine, journal.as_ref());
+    let siblings = pairs::SiblingIndex::new(&files);
+    let videos = motion_photos::VideoIndex::new(&files);
+    let mut handled = HashSet::new();
+    let settle_time = std::time::Duration::from_secs(config.settle_time);
+
+    // Files are read and hashed by a pool of workers, but only ever placed by this thread (in the
+    // order they were found) so that conflicts are resolved the same way whatever the thread count.
+    pipeline::ordered(
+        &files,
+        pipeline::threads(config.threads),
+        |path| match settle::check(path, settle_time, config.verify_complete) {
+            Some(reason) => Prepared {
+                unsettled: Some(reason),
+                ..Default::default()
+            },
+            None => {
+                let metadata = image::load(path);
+
+                // Every JPEG which could be this RAW's partner is loaded, since only the placing
+                // thread knows which of them have already been claimed.
+                let mut pairs = Vec::new();
+                if let Some(Ok(metadata)) = metadata.as_ref().filter(|_| image::is_raw(path)) {
+                    pairs = siblings
+                        .siblings(path)
+                        .filter(|sibling| pairs::is_jpeg(sibling))
+                        .filter_map(|jpeg| match image::load(jpeg) {
+                            Some(Ok(jpeg_metadata))
+                                if pairs::is_same_capture(metadata, &jpeg_metadata) =>
+                            {
+                                let sidecars =
+                                    sidecars::find_sidecars(jpeg, &config.sidecars.extensions);
+                                Some((jpeg_metadata, sidecars))
+                            }
+                            _ => None,
+                        })
+                        .collect();
                 }
 
-                if target == entry.path() {
-                    continue;
+                Prepared {
+                    unsettled: None,
+                    metadata,
+                    hash: (config.ledger.enabled || config.index.enabled || config.journal.enabled)
+                        .then(|| hashes.hash(path).ok())
+                        .flatten(),
+                    perceptual_hash: config
+                        .similar
+                        .enabled
+                        .then(|| perceptual::dhash(path))
+                        .flatten(),
+                    sidecars: sidecars::find_sidecars(path, &config.sidecars.extensions),
+                    video: config
+                        .motion_photos
+                        .extract_video
+                        .then(|| motion_photos::embedded_video(path).ok().flatten())
+                        .flatten(),
+                    pairs,
                 }
+            }
+        },
+        |path, prepared| {
+            if handled.contains(path) {
+                return Ok(());
+            }
+
+            // Files which are still being written are left for a later run, once they have settled.
+            if let Some(reason) = prepared.unsettled {
+                info!("skip '{}' ({})", path.display(), reason);
+                return Ok(());
+            }
+
+            // Photos which were imported before and have since been deleted from the library are
+            // recognised by their content, so that re-inserting a memory card doesn't bring them back.
+            let hash = prepared.hash;
+            if let Some(ledger) = indexes.ledger.as_ref() {
+                let entry = hash.as_deref().and_then(|hash| ledger.get(hash));
+                if let Some(entry) = entry.filter(|_| {
+                    config.ledger.previously_imported != config::PreviouslyImported::Reimport
+                }) {
+                    info!(
+                        "previously imported '{}' as '{}'",
+                        path.display(),
+                        entry.path.display()
+                    );
 
-                if !args.audit {
-                    std::fs::create_dir_all(target.parent().unwrap()).wrap_err_as_user(
-                        format!("Unable to create directory '{}'.", target.parent().unwrap().display()),
-                        &["Make sure that you've got permission to create this directory and try again."],
-                    )?;
-
-                    let written_path = conflict_manager::rename_no_conflict(entry.path(), &target)
-                        .wrap_err_as_user(
-                            format!(
-                                "Failed to move '{}' to '{}'",
-                                entry.path().display(),
-                                target.display()
-                            ),
-                            &["Make sure that you have permission to move the image and try again."],
+                    let delete =
+                        config.ledger.previously_imported == config::PreviouslyImported::Delete;
+                    if let Some(plan) = planned.as_mut().filter(|_| delete) {
+                        plan.imports.push(plan::PlannedImport {
+                            reason: format!("previously imported as '{}'", entry.path.display()),
+                            metadata: Default::default(),
+                            files: vec![plan::PlannedFile::new(
+                                path,
+                                plan::PlannedAction::Discard { duplicate: None },
+                                hash.clone(),
+                            )
+                            .wrap_system_err(
+                                format!("Unable to read '{}'.", path.display()),
+                                &["Make sure that you have permission to read the image and try again."],
+                            )?],
+                        });
+                    } else if delete {
+                        info!("rm '{}'", path.display());
+                        conflicts.remove(path).wrap_user_err(
+                            format!("Failed to remove '{}'", path.display()),
+                            &["Make sure that you have permission to remove the image and try again."],
                         )?;
+                    }
+
+                    return Ok(());
+                }
+            }
+
+            let mut metadata = match prepared.metadata {
+                Some(Ok(metadata)) => metadata,
+                Some(Err(e)) => {
+                    warn!("Error: {}", e);
+                    return Ok(());
+                }
+                None => return Ok(()),
+            };
 
+            let mut pair = prepared
+                .pairs
+                .into_iter()
+                .find(|(jpeg_metadata, _)| !handled.contains(jpeg_metadata.path));
+
+            if let Some((jpeg_metadata, _)) = pair.as_mut() {
+                metadata.pair_role = Some(pairs::ROLE_RAW);
+                jpeg_metadata.pair_role = Some(pairs::ROLE_JPEG);
+            }
+
+            let mut target = target_path(config, template.render(&metadata), path);
+            let mut reason = format!("rendered from the template '{}'", config.template);
+
+            // Images which already exist anywhere in the library are directed to that copy, so that
+            // they are removed as duplicates rather than being imported under a different name.
+            let mut redirected = false;
+            if let Some(index) = indexes.index.as_ref() {
+                let existing = hash
+                    .as_deref()
+                    .and_then(|hash| index.find(hash, path, |existing| conflicts.exists(existing)));
+                if let Some(existing) = existing {
+                    reason = format!("identical to '{}' in the library", existing.display());
+                    target = existing;
+                    redirected = true;
+                }
+            }
+
+            // Near-duplicates aren't removed, since only a person can tell which copy is worth keeping.
+            let perceptual_hash = prepared.perceptual_hash;
+            if let Some(similar) = indexes.similar.as_ref().filter(|_| !redirected) {
+                let existing = perceptual_hash
+                    .and_then(|hash| similar.find_similar(hash, config.similar.max_distance, path))
+                    .filter(|(existing, _)| conflicts.exists(existing));
+
+                if let Some((existing, distance)) = existing {
                     info!(
-                        "mv '{}' '{}'",
-                        entry.path().display(),
-                        written_path.display()
+                        "similar '{}' to '{}' (distance {})",
+                        path.display(),
+                        existing.display(),
+                        distance
                     );
-                    written_files.insert(written_path);
+
+                    if config.similar.action == config::SimilarAction::Route {
+                        if let Ok(relative) = target.strip_prefix(&config.target) {
+                            reason = format!(
+                                "similar to '{}' (distance {})",
+                                existing.display(),
+                                distance
+                            );
+                            target = config.target.join(&config.similar.directory).join(relative);
+                        }
+                    }
+                }
+            }
+
+            if target == *path {
+                return Ok(());
+            }
+
+            // Sidecars which an earlier image already claimed (or which an audit has already moved)
+            // stay with that image, even when they are still in the source directory.
+            let unclaimed =
+                |sidecar: &PathBuf| !handled.contains(sidecar) && conflicts.exists(sidecar);
+
+            let group = file_group::FileGroup::new(path.as_path())
+                .with_companions(prepared.sidecars.into_iter().filter(unclaimed))
+                .with_companions(videos.find(&metadata, &siblings, &handled));
+            let mut import = Import {
+                moves: group.moves(&target),
+                reason,
+                hashes: hash
+                    .iter()
+                    .map(|hash| (path.clone(), hash.clone()))
+                    .collect(),
+                ..Default::default()
+            };
+
+            // Takeout sidecars keep Google's `<name>.<ext>.json` naming so that they can be found again later.
+            // Edited copies share their original's sidecar, which is only ever claimed by the first of them.
+            if let Some(sidecar) =
+                image::find_takeout_sidecar(path).filter(|sidecar| !handled.contains(sidecar))
+            {
+                match config.takeout.sidecar {
+                    config::TakeoutSidecarAction::Move => import.moves.push((
+                        sidecar,
+                        target.with_file_name(format!(
+                            "{}.json",
+                            target.file_name().unwrap_or_default().to_string_lossy()
+                        )),
+                    )),
+                    config::TakeoutSidecarAction::Delete => import.discards.push(sidecar),
+                }
+            }
+
+            if let Some(video) = prepared.video {
+                let extension = match path.extension().and_then(|e| e.to_str()) {
+                    Some(ext) if ext.chars().all(|c| c.is_ascii_lowercase()) => "mp4",
+                    _ => "MP4",
+                };
+
+                import
+                    .extractions
+                    .push((path.clone(), video, target.with_extension(extension)));
+            }
+
+            if let Some((jpeg_metadata, jpeg_sidecars)) = pair {
+                let jpeg_group = file_group::FileGroup::new(jpeg_metadata.path).with_companions(
+                    jpeg_sidecars
+                        .into_iter()
+                        .filter(unclaimed)
+                        .chain(videos.find(&jpeg_metadata, &siblings, &handled))
+                        .filter(|companion| !group.companions.contains(companion)),
+                );
+
+                if config.pairs.discard_jpeg {
+                    import
+                        .discards
+                        .extend(jpeg_group.files().map(|p| p.to_path_buf()));
                 } else {
-                    info!("mv '{}' '{}'", entry.path().display(), target.display());
+                    // Both halves of the pair share the RAW's name, even if they are routed to different folders.
+                    let jpeg_target =
+                        target_path(config, template.render(&jpeg_metadata), jpeg_metadata.path)
+                            .with_file_name(target.file_name().unwrap_or_default())
+                            .with_extension(jpeg_metadata.path.extension().unwrap_or_default());
+                    import.moves.extend(jpeg_group.moves(jpeg_target));
+                }
+            }
+
+            let unsettled = import.moves.iter().skip(1).find_map(|(source, _)| {
+                settle::check(source, settle_time, config.verify_complete)
+                    .map(|reason| (source, reason))
+            });
+            if let Some((source, reason)) = unsettled {
+                info!(
+                    "skip '{}' ('{}' {})",
+                    path.display(),
+                    source.display(),
+                    reason
+                );
+                return Ok(());
+            }
+
+            handled.extend(import.moves.iter().map(|(source, _)| source.clone()));
+            handled.extend(import.discards.iter().cloned());
+
+            let placements = import.apply(config, audit, &conflicts, None)?;
+
+            // Plans record where each file ended up once its conflicts were resolved, so that
+            // applying the plan later makes exactly the changes which were reviewed.
+            if let Some(plan) = planned.as_mut() {
+                plan.imports.push(
+                    import
+                        .plan(config, &metadata, hashes, &conflicts, &placements)
+                        .wrap_system_err(
+                            format!("Unable to plan the import of '{}'.", path.display()),
+                            &["Make sure that you have permission to read the image and try again."],
+                        )?,
+                );
+            }
+
+            indexes.record(
+                config,
+                context,
+                &conflicts,
+                &written_paths(&placements),
+                hash,
+                perceptual_hash,
+            );
+
+            Ok(())
+        },
+    )?;
+
+    context.finish(journal);
+    Ok(())
+}
+
+/// Imports new images as they arrive in the source directory, passing each batch of files which
+/// have finished being written through the same pipeline as a normal run.
+#[cfg(target_os = "linux")]
+fn watch(
+    config: &config::Config,
+    quarantine: &quarantine::Quarantine,
+    audit: bool,
+) -> Result<(), errors::Error> {
+    // Files are left to settle for at least as long as a normal run would wait for them.
+    let settle =
+        std::time::Duration::from_secs(config.watch.debounce_seconds.max(config.settle_time));
+    let rescan = std::time::Duration::from_secs(config.watch.rescan_minutes * 60);
+
+    let mut watcher = watch::Watcher::new(&config.source, settle).wrap_system_err(
+        format!("Unable to watch '{}' for new images.", config.source.display()),
+        &["Make sure that the source directory exists and that you have not reached the limit on inotify watches (fs.inotify.max_user_watches)."],
+    )?;
+
+    // The library is only indexed once, with each batch keeping the indexes up to date as it imports.
+    let context = Context::open(config, audit)?;
+    let mut indexes = Indexes::open(config, quarantine, &context)?;
+
+    info!("Watching '{}' for new images.", config.source.display());
+
+    // The first scan picks up anything which arrived while we weren't watching.
+    let mut last_scan: Option<std::time::Instant> = None;
+    loop {
+        let overflowed = watcher.poll().wrap_system_err(
+            "Unable to read changes to the source directory.",
+            &["Try restarting `imgsort watch`, which will rescan the source directory."],
+        )?;
+
+        let rescanning = overflowed || last_scan.is_none_or(|scan| scan.elapsed() >= rescan);
+        let mut files = if rescanning {
+            last_scan = Some(std::time::Instant::now());
+
+            let mut files = source_files(config);
+            files.retain(|path| !watcher.is_pending(path));
+            files
+        } else {
+            let mut files = watcher.ready();
+            files.retain(|path| is_importable(config, path));
+            files
+        };
+
+        // Copies never consume their sources, so every rescan finds the files which were already
+        // imported again. They are recognised by their (cached) hashes before anything else is done.
+        let skip_imported = config.ledger.previously_imported == config::PreviouslyImported::Skip;
+        if let Some(ledger) = indexes.ledger.as_ref().filter(|_| skip_imported) {
+            files.retain(|path| {
+                let hash = context.hashes.hash(path);
+                !hash.is_ok_and(|hash| ledger.get(&hash).is_some())
+            });
+        }
+
+        // Files which are still being written are left for the watcher to pick up once they settle.
+        if rescanning && !files.is_empty() {
+            let writing = watch::open_for_writing();
+            files.retain(|path| !writing.contains(path));
+        }
+
+        if !files.is_empty() {
+            if let Err(e) = import_images(config, quarantine, &context, &mut indexes, None, files) {
+                error!("{}", human_errors::pretty(&e));
+            }
+        }
+
+        std::thread::sleep(watch::POLL_INTERVAL);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(
+    _config: &config::Config,
+    _quarantine: &quarantine::Quarantine,
+    _audit: bool,
+) -> Result<(), errors::Error> {
+    Err(human_errors::user(
+        "Watching the source directory is only supported on Linux.",
+        &["Run imgsort periodically (for example, from cron) instead."],
+    ))
+}
+
+/// Applies a plan created by `imgsort plan`, re-validating every file before it is touched.
+fn apply_plan(
+    config: &config::Config,
+    quarantine: &quarantine::Quarantine,
+    path: &Path,
+    audit: bool,
+) -> Result<(), errors::Error> {
+    let plan = plan::Plan::load(path).wrap_user_err(
+        format!("Unable to read the plan in '{}'.", path.display()),
+        &["Make sure that the file was created by `imgsort plan` and is still valid JSON."],
+    )?;
+
+    let context = Context::open(config, audit)?;
+    let mut indexes = Indexes::open(config, quarantine, &context)?;
+
+    let journal = context.begin_journal(config)?;
+    let conflicts = context.conflicts(config, quarantine, journal.as_ref());
+
+    let mut skipped = 0;
+    for planned in plan.imports.iter() {
+        // Images are skipped as a whole if any of their files (or the library files they were placed
+        // against) have changed, so that groups are never split up.
+        let changes = planned.changes(&conflicts);
+        if !changes.is_empty() {
+            for change in changes {
+                warn!("Skipping an import because {}.", change);
+            }
+
+            skipped += 1;
+            continue;
+        }
+
+        let (import, placements) = Import::from_plan(planned);
+        if import.moves.is_empty() {
+            for discard in import.discards.iter() {
+                info!("rm '{}'", discard.display());
+                conflicts.remove(discard).wrap_user_err(
+                    format!("Failed to remove '{}'", discard.display()),
+                    &["Make sure that you have permission to remove the image and try again."],
+                )?;
+            }
+
+            continue;
+        }
+
+        // Plans don't record perceptual hashes, so the primary file's is computed before it is moved.
+        let primary = planned.files.first();
+        let perceptual_hash = primary
+            .filter(|_| config.similar.enabled)
+            .and_then(|file| perceptual::dhash(&file.source));
+
+        let placements = import.apply(config, audit, &conflicts, Some(&placements))?;
+        indexes.record(
+            config,
+            &context,
+            &conflicts,
+            &written_paths(&placements),
+            primary.and_then(|file| file.hash.clone()),
+            perceptual_hash,
+        );
+    }
+
+    context.finish(journal);
+
+    if skipped > 0 {
+        warn!(
+            "Skipped {} imports whose files changed after the plan was created.",
+            skipped
+        );
+    }
+
+    Ok(())
+}
+
+/// Records the files written by an import in the ledger, where `hash` is the primary file's hash (if known).
+/// Audits only record them in memory, so that the rest of the run behaves as it would for real.
+fn record_imported(
+    config: &config::Config,
+    ledger: &mut import_ledger::ImportLedger,
+    conflicts: &conflict_manager::ConflictManager,
+    hashes: &hash_cache::HashCache,
+    written_paths: &[PathBuf],
+    hash: Option<String>,
+    audit: bool,
+) {
+    for (i, written_path) in written_paths.iter().enumerate() {
+        if sidecars::is_sidecar(written_path, &config.sidecars.extensions) {
+            continue;
+        }
+
+        let written_hash = match i {
+            0 => hash.clone(),
+            _ => hashes.hash(conflicts.content(written_path)).ok(),
+        };
+
+        if let Some(written_hash) = written_hash {
+            if audit {
+                ledger.simulate_record(&written_hash, written_path);
+            } else if let Err(e) = ledger.record(&written_hash, written_path) {
+                warn!(
+                    "Unable to record that '{}' was imported: {}",
+                    written_path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn open_hash_cache(
+    config: &config::Config,
+    read_only: bool,
+) -> Result<hash_cache::HashCache, errors::Error> {
+    hash_cache::HashCache::open(config.hash_cache.backend, &config.target, read_only)
+        .wrap_system_err(
+            "Unable to open the hash cache.",
+            &["Make sure that you have permission to read your target directory and that your hash cache backend is supported on this platform."],
+        )
+}
+
+/// The indexes of the files already in the library, which are built once and then kept up to date
+/// as images are imported (so that watch mode doesn't rescan the library for every batch).
+struct Indexes {
+    index: Option<library_index::LibraryIndex>,
+    ledger: Option<import_ledger::ImportLedger>,
+    similar: Option<perceptual::PerceptualIndex>,
+}
+
+impl Indexes {
+    fn open(
+        config: &config::Config,
+        quarantine: &quarantine::Quarantine,
+        context: &Context,
+    ) -> Result<Self, errors::Error> {
+        let excluded = |path: &Path| {
+            path.starts_with(&config.source)
+                || path.starts_with(quarantine.directory())
+                || (config.synology && is_synology_index(path))
+        };
+        let threads = pipeline::threads(config.threads);
+
+        let mut index = None;
+        if config.index.enabled {
+            let library = library_index::LibraryIndex::open(
+                &config.target,
+                &context.hashes,
+                threads,
+                excluded,
+            )
+            .wrap_system_err(
+                format!(
+                    "Unable to build the index of the library in '{}'.",
+                    config.target.display()
+                ),
+                &["Make sure that you have permission to read the files in your target directory and try again."],
+            )?;
+
+            if !context.is_audit() {
+                library.save().wrap_system_err(
+                    "Unable to save the index of your library.",
+                    &["Make sure that you have permission to write to your target directory and try again."],
+                )?;
+            }
+
+            index = Some(library);
+        }
+
+        let mut ledger = None;
+        if config.ledger.enabled {
+            ledger = Some(
+                import_ledger::ImportLedger::open(&config.target).wrap_system_err(
+                    "Unable to open the ledger of previously imported files.",
+                    &["Make sure that you have permission to read your target directory and try again."],
+                )?,
+            );
+        }
+
+        let mut similar = None;
+        if config.similar.enabled {
+            let library = perceptual::PerceptualIndex::open(&config.target, threads, excluded)
+                .wrap_system_err(
+                    format!(
+                        "Unable to build the perceptual index of the library in '{}'.",
+                        config.target.display()
+                    ),
+                    &["Make sure that you have permission to read the files in your target directory and try again."],
+                )?;
+
+            if !context.is_audit() {
+                library.save().wrap_system_err(
+                    "Unable to save the perceptual index of your library.",
+                    &["Make sure that you have permission to write to your target directory and try again."],
+                )?;
+            }
+
+            similar = Some(library);
+        }
+
+        Ok(Indexes {
+            index,
+            ledger,
+            similar,
+        })
+    }
+
+    /// Adds the files written by an import to each of the indexes, where `hash` and
+    /// `perceptual_hash` are those of the primary file (if known). Audits only add them in memory,
+    /// so that the rest of the run behaves as it would for real.
+    fn record(
+        &mut self,
+        config: &config::Config,
+        context: &Context,
+        conflicts: &conflict_manager::ConflictManager,
+        written_paths: &[PathBuf],
+        mut hash: Option<String>,
+        perceptual_hash: Option<u64>,
+    ) {
+        let hashes = &context.hashes;
+        let audit = context.is_audit();
+
+        if let Some(ledger) = self.ledger.as_mut() {
+            record_imported(
+                config,
+                ledger,
+                conflicts,
+                hashes,
+                written_paths,
+                hash.clone(),
+                audit,
+            );
+        }
+
+        if let Some(index) = self.index.as_mut() {
+            for (i, written_path) in written_paths.iter().enumerate() {
+                let hash = if i == 0 { hash.take() } else { None };
+                if audit {
+                    let hash = hash.or_else(|| hashes.hash(conflicts.content(written_path)).ok());
+                    if let Some(hash) = hash {
+                        index.simulate_insert(written_path, hash);
+                    }
+                } else if let Err(e) = index.insert(written_path, hash, hashes) {
+                    warn!(
+                        "Unable to add '{}' to the library index: {}",
+                        written_path.display(),
+                        e
+                    );
                 }
             }
-            Some(Err(e)) => warn!("Error: {}", e),
-            None => {}
         }
+
+        if let (Some(similar), Some(hash)) = (self.similar.as_mut(), perceptual_hash) {
+            if let Some(written_path) = written_paths.first() {
+                if audit {
+                    similar.simulate_insert(written_path, hash);
+                } else if let Err(e) = similar.insert(written_path, hash) {
+                    warn!(
+                        "Unable to add '{}' to the perceptual index: {}",
+                        written_path.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The state shared by every change a run makes to the library: the hash cache used to compare
+/// files and, for audits, the overlay in which those changes are simulated.
+struct Context {
+    hashes: hash_cache::HashCache,
+    /// Audits make their changes to an overlay of the filesystem, so that later decisions see them
+    /// just as they would in a real run.
+    overlay: Option<overlay::Overlay>,
+}
+
+impl Context {
+    fn open(config: &config::Config, audit: bool) -> Result<Self, errors::Error> {
+        Ok(Context {
+            hashes: open_hash_cache(config, audit)?,
+            overlay: audit.then(overlay::Overlay::default),
+        })
+    }
+
+    fn is_audit(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    /// Starts the journal in which a run's changes are recorded, unless it is disabled or this is an audit.
+    fn begin_journal(
+        &self,
+        config: &config::Config,
+    ) -> Result<Option<journal::Journal>, errors::Error> {
+        if !config.journal.enabled || self.is_audit() {
+            return Ok(None);
+        }
+
+        journal::Journal::begin(&config.target)
+            .map(Some)
+            .wrap_system_err(
+                "Unable to start the journal for this run.",
+                &["Make sure that you have permission to write to your target directory and try again."],
+            )
+    }
+
+    /// Creates the conflict manager through which a run makes (or simulates) its changes.
+    fn conflicts<'a>(
+        &'a self,
+        config: &'a config::Config,
+        quarantine: &'a quarantine::Quarantine,
+        journal: Option<&'a journal::Journal>,
+    ) -> conflict_manager::ConflictManager<'a> {
+        let mut conflicts =
+            conflict_manager::ConflictManager::new(config, &self.hashes, quarantine);
+        if let Some(journal) = journal {
+            conflicts = conflicts.with_journal(journal);
+        }
+        if let Some(overlay) = self.overlay.as_ref() {
+            conflicts = conflicts.with_overlay(overlay);
+        }
+
+        conflicts
+    }
+
+    /// Saves the hash cache and finishes the run's journal.
+    fn finish(&self, journal: Option<journal::Journal>) {
+        if let Err(e) = self.hashes.save() {
+            warn!("Unable to save the hash cache: {}", e);
+        }
+
+        if let Some(journal) = journal {
+            finish_journal(journal);
+        }
+    }
+}
+
+fn finish_journal(journal: journal::Journal) {
+    let run_id = journal.run_id().to_string();
+    match journal.finish() {
+        Ok(true) => info!(
+            "Recorded this run as '{}', which can be reversed with `imgsort undo {}`.",
+            run_id, run_id
+        ),
+        Ok(false) => {}
+        Err(e) => warn!("Unable to finish the journal for this run: {}", e),
     }
+}
 
+fn dedupe(
+    config: &config::Config,
+    quarantine: &quarantine::Quarantine,
+    directory: &Path,
+    keep: config::DedupeKeep,
+    action: config::DedupeAction,
+    audit: bool,
+) -> Result<(), errors::Error> {
+    let template = template_context(&config.template);
+    let hashes = open_hash_cache(config, audit)?;
+
+    // Duplicates are always moved into quarantine, even when images are usually copied into the library.
+    let conflicts = conflict_manager::ConflictManager::new(config, &hashes, quarantine)
+        .with_import_mode(config::ImportMode::Move);
+
+    let groups = dedupe::find_duplicates(directory, &hashes, |path| {
+        path.starts_with(quarantine.directory()) || (config.synology && is_synology_index(path))
+    });
+
+    let mut duplicates = 0;
+    let mut reclaimed = 0;
+    for mut group in groups {
+        let keeper = group.remove(dedupe::pick_keeper(
+            &group,
+            keep,
+            |path| match image::load(path) {
+                Some(Ok(metadata)) => Some(target_path(config, template.render(&metadata), path)),
+                _ => None,
+            },
+        ));
+
+        duplicates += group.len();
+        reclaimed += dedupe::resolve(&keeper, &group, action, &conflicts, config.removal.method, audit)
+            .wrap_user_err(
+                format!("Failed to remove the duplicates of '{}'.", keeper.display()),
+                &["Make sure that you have permission to modify the files in this directory and try again."],
+            )?;
+    }
+
+    if let Err(e) = hashes.save() {
+        warn!("Unable to save the hash cache: {}", e);
+    }
+
+    info!(
+        "Found {} duplicate files in '{}'.",
+        duplicates,
+        directory.display()
+    );
+
+    if action == config::DedupeAction::Hardlink {
+        info!(
+            "Reclaimed {} by replacing duplicates with hardlinks.",
+            dedupe::format_size(reclaimed)
+        );
+    }
     Ok(())
 }
+
+fn purge(
+    config: &config::Config,
+    quarantine: &quarantine::Quarantine,
+    older_than: Option<u64>,
+    audit: bool,
+) -> Result<(), errors::Error> {
+    let days = older_than.unwrap_or(config.quarantine.retention_days);
+    let purged = quarantine
+        .purge(std::time::Duration::from_secs(days * 86_400), audit)
+        .wrap_user_err(
+            format!(
+                "Failed to purge the quarantined images in '{}'.",
+                quarantine.directory().display()
+            ),
+            &["Make sure that you have permission to remove files from your quarantine directory and try again."],
+        )?;
+
+    info!(
+        "Purged {} images quarantined more than {} days ago.",
+        purged.len(),
+        days
+    );
+
+    let days = config.journal.retention_days;
+    let pruned = journal::prune(
+        &config.target,
+        std::time::Duration::from_secs(days * 86_400),
+        audit,
+    )
+    .wrap_user_err(
+        "Failed to remove the journals of old runs.",
+        &["Make sure that you have permission to remove files from your target directory and try again."],
+    )?;
+
+    if !pruned.is_empty() {
+        info!(
+            "Removed the journals of {} runs from more than {} days ago.",
+            pruned.len(),
+            days
+        );
+    }
+
+    Ok(())
+}
+
+fn undo(config: &config::Config, run_id: Option<String>, audit: bool) -> Result<(), errors::Error> {
+    let runs = journal::runs(&config.target).wrap_system_err(
+        "Unable to read the journals of previous runs.",
+        &["Make sure that you have permission to read your target directory and try again."],
+    )?;
+
+    let run_id = match run_id {
+        Some(run_id) if runs.contains(&run_id) => run_id,
+        Some(run_id) => {
+            return Err(human_errors::user(
+                format!("There is no journal for the run '{}'.", run_id),
+                &["Make sure that you have entered the run ID printed at the end of the run, and that it has not already been undone or purged."],
+            ))
+        }
+        None => runs.last().cloned().ok_or_else(|| {
+            human_errors::user(
+                "There are no runs which can be undone.",
+                &["Make sure that `journal.enabled` is set in your configuration file, since runs can only be undone if they were recorded."],
+            )
+        })?,
+    };
+
+    let actions = journal::load(&config.target, &run_id).wrap_system_err(
+        format!("Unable to read the journal for the run '{}'.", run_id),
+        &["Make sure that you have permission to read your target directory and try again."],
+    )?;
+
+    let problems = journal::check(&actions);
+    if !problems.is_empty() {
+        for problem in problems.iter() {
+            warn!("{}", problem);
+        }
+
+        return Err(human_errors::user(
+            format!(
+                "Unable to safely undo the run '{}' because {} files have changed since it ran.",
+                run_id,
+                problems.len()
+            ),
+            &["Restore or move aside the files listed above and try again."],
+        ));
+    }
+
+    journal::undo(&config.target, &run_id, &actions, audit).wrap_user_err(
+        format!("Failed to undo the run '{}'.", run_id),
+        &["Make sure that you have permission to move files in your source and target directories, then run this command again to finish undoing the run."],
+    )?;
+
+    // Files which were moved back out of the library shouldn't be treated as previously imported.
+    if config.ledger.enabled && !audit {
+        let hashes: HashSet<&str> = actions
+            .iter()
+            .filter_map(|action| match action {
+                journal::Action::Move { hash, .. } | journal::Action::Create { hash, .. } => {
+                    Some(hash.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+
+        let result = import_ledger::ImportLedger::open(&config.target).and_then(|mut ledger| {
+            ledger.forget(|hash, _| hashes.contains(hash));
+            ledger.save()
+        });
+
+        if let Err(e) = result {
+            warn!(
+                "Unable to update the ledger of previously imported files: {}",
+                e
+            );
+        }
+    }
+
+    info!(
+        "Undid {} changes made by the run '{}'.",
+        actions.len(),
+        run_id
+    );
+    Ok(())
+}
+
+fn forget(config: &config::Config, entries: &[String], audit: bool) -> Result<(), errors::Error> {
+    let mut ledger = import_ledger::ImportLedger::open(&config.target).wrap_system_err(
+        "Unable to open the ledger of previously imported files.",
+        &["Make sure that you have permission to read your target directory and try again."],
+    )?;
+
+    let mut hashes = Vec::new();
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = Path::new(entry);
+        if path.is_file() {
+            hashes.push(conflict_manager::file_hash(path).wrap_user_err(
+                format!("Unable to hash '{}'.", path.display()),
+                &["Make sure that you have permission to read this file and try again."],
+            )?);
+        } else {
+            paths.push(path.strip_prefix(&config.target).unwrap_or(path));
+            hashes.push(entry.to_lowercase());
+        }
+    }
+
+    let forgotten = ledger.forget(|hash, entry| {
+        hashes.iter().any(|h| h == hash) || paths.iter().any(|path| entry.path.starts_with(path))
+    });
+
+    for (hash, entry) in forgotten.iter() {
+        info!("forget '{}' ({})", entry.path.display(), hash);
+    }
+
+    if !audit {
+        ledger.save().wrap_system_err(
+            "Unable to save the ledger of previously imported files.",
+            &["Make sure that you have permission to write to your target directory and try again."],
+        )?;
+    }
+
+    info!("Forgot {} previously imported files.", forgotten.len());
+    Ok(())
+}
+
+fn template_context(template: &str) -> template::TemplateContext {
+    template::TemplateContext::new(template)
+        .with_transform("lowercase", template::transform(|s| s.to_lowercase()))
+        .with_transform("uppercase", template::transform(|s| s.to_uppercase()))
+        .with_transform(
+            "path_safe",
+            template::transform(|s| s.replace(['/', '\\', ':', ';', '#'], "")),
+        )
+        .with_transform("trim", template::transform(|s| s.trim().to_owned()))
+}
+
+/// Determines whether a file belongs to the index directories which Synology NAS devices create.
+fn is_synology_index(path: &Path) -> bool {
+    path.components()
+        .any(|p| p.as_os_str().to_string_lossy() == "@eaDir")
+}
+
+fn target_path(config: &config::Config, rendered: String, source: &Path) -> PathBuf {
+    let mut target = config.target.join(rendered);
+
+    if let Some(ext) = source.extension() {
+        target = target.with_extension(ext);
+    }
+
+    target
+}
+
+/// The information about a file which is gathered by the worker pool before it is imported.
+#[derive(Default)]
+struct Prepared<'a> {
+    /// Why the file isn't ready to be imported yet, in which case nothing else is gathered.
+    unsettled: Option<String>,
+    metadata: Option<Result<image::Metadata<'a>, errors::Error>>,
+    hash: Option<String>,
+    perceptual_hash: Option<u64>,
+    sidecars: Vec<PathBuf>,
+    /// The video embedded within a motion photo, if it is to be extracted.
+    video: Option<motion_photos::EmbeddedVideo>,
+    /// The JPEGs which could be the other half of a RAW+JPEG pair, along with their sidecars.
+    pairs: Vec<(image::Metadata<'a>, Vec<PathBuf>)>,
+}
+
+/// The set of changes required to import a single image, along with the companion
+/// files which travel with it.
+#[derive(Default)]
+struct Import {
+    moves: Vec<(PathBuf, PathBuf)>,
+    extractions: Vec<(PathBuf, motion_photos::EmbeddedVideo, PathBuf)>,
+    discards: Vec<PathBuf>,
+    reason: String,
+    /// The hashes which are already known for some of the files being moved, so that they don't
+    /// need to be hashed again once they have been placed.
+    hashes: HashMap<PathBuf, String>,
+}
+
+/// The paths to which an import wrote files, in the same order as its placements.
+fn written_paths(placements: &[conflict_manager::Placement]) -> Vec<PathBuf> {
+    placements
+        .iter()
+        .filter_map(|placement| match placement {
+            conflict_manager::Placement::Moved(path)
+            | conflict_manager::Placement::Replaced { path, .. } => Some(path.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Import {
+    /// Rebuilds an import from a plan, along with the placement which was decided for each of
+    /// its moves and extractions (in that order) so that they can be replayed by [Import::apply].
+    fn from_plan(planned: &plan::PlannedImport) -> (Self, Vec<conflict_manager::Placement>) {
+        use conflict_manager::Placement;
+
+        let mut import = Import {
+            reason: planned.reason.clone(),
+            ..Default::default()
+        };
+        let mut placements = Vec::new();
+        let mut extractions = Vec::new();
+
+        for file in planned.files.iter() {
+            let source = file.source.clone();
+            if let Some(hash) = file.hash.as_ref() {
+                import.hashes.insert(source.clone(), hash.clone());
+            }
+
+            let placement = match &file.action {
+                plan::PlannedAction::Import { destination } => {
+                    Placement::Moved(destination.clone())
+                }
+                plan::PlannedAction::Replace {
+                    destination,
+                    quarantine,
+                    ..
+                } => Placement::Replaced {
+                    path: destination.clone(),
+                    previous: destination.clone(),
+                    quarantined: quarantine.clone(),
+                },
+                plan::PlannedAction::Quarantine { destination } => {
+                    Placement::Quarantined(destination.clone())
+                }
+                plan::PlannedAction::Discard {
+                    duplicate: Some(duplicate),
+                } => Placement::Removed(duplicate.path.clone()),
+                plan::PlannedAction::Discard { duplicate: None } => {
+                    import.discards.push(source);
+                    continue;
+                }
+                plan::PlannedAction::Skip => Placement::Skipped,
+            };
+
+            let destination = file.destination().unwrap_or(&file.source).to_path_buf();
+            match file.video {
+                // Videos which weren't going to be placed anywhere are never extracted.
+                Some(_) if file.destination().is_none() => {}
+                Some(video) => extractions.push((
+                    (
+                        source,
+                        motion_photos::EmbeddedVideo {
+                            offset: video.offset,
+                            length: video.length,
+                        },
+                        destination,
+                    ),
+                    placement,
+                )),
+                None => {
+                    import.moves.push((source, destination));
+                    placements.push(placement);
+                }
+            }
+        }
+
+        for (extraction, placement) in extractions {
+            import.extractions.push(extraction);
+            placements.push(placement);
+        }
+
+        (import, placements)
+    }
+
+    /// Describes the changes which [Import::apply] made (or simulated) with the given `placements`,
+    /// along with the metadata which led to them and the state of any library files they depend on.
+    fn plan(
+        &self,
+        config: &config::Config,
+        metadata: &image::Metadata,
+        hashes: &hash_cache::HashCache,
+        conflicts: &conflict_manager::ConflictManager,
+        placements: &[conflict_manager::Placement],
+    ) -> std::io::Result<plan::PlannedImport> {
+        use conflict_manager::Placement;
+
+        let action = |placement: &Placement| -> std::io::Result<plan::PlannedAction> {
+            Ok(match placement {
+                Placement::Moved(path) => plan::PlannedAction::Import {
+                    destination: path.clone(),
+                },
+                Placement::Replaced {
+                    path,
+                    previous,
+                    quarantined,
+                } => plan::PlannedAction::Replace {
+                    destination: path.clone(),
+                    existing: hashes.hash(previous)?,
+                    quarantine: quarantined.clone(),
+                },
+                Placement::Quarantined(path) => plan::PlannedAction::Quarantine {
+                    destination: path.clone(),
+                },
+                Placement::Removed(path) => plan::PlannedAction::Discard {
+                    duplicate: Some(plan::Existing {
+                        path: path.clone(),
+                        hash: conflicts.hash(path)?,
+                    }),
+                },
+                Placement::Skipped => plan::PlannedAction::Skip,
+            })
+        };
+
+        let mut files = Vec::new();
+        for ((source, _), placement) in self.moves.iter().zip(placements) {
+            files.push(plan::PlannedFile::new(
+                source,
+                action(placement)?,
+                hashes.hash(source).ok(),
+            )?);
+        }
+
+        for ((source, video, _), placement) in self
+            .extractions
+            .iter()
+            .zip(placements.iter().skip(self.moves.len()))
+        {
+            // A video which is identical to one already in the library is simply never extracted.
+            let action = match placement {
+                Placement::Removed(_) => plan::PlannedAction::Skip,
+                placement => action(placement)?,
+            };
+
+            let video = plan::PlannedVideo {
+                offset: video.offset,
+                length: video.length,
+            };
+            files.push(plan::PlannedFile::video(source, action, video)?);
+        }
+
+        if config.import_mode == config::ImportMode::Move {
+            for discard in self.discards.iter() {
+                let action = plan::PlannedAction::Discard { duplicate: None };
+                files.push(plan::PlannedFile::new(
+                    discard,
+                    action,
+                    hashes.hash(discard).ok(),
+                )?);
+            }
+        }
+
+        Ok(plan::PlannedImport {
+            reason: self.reason.clone(),
+            metadata: metadata.into(),
+            files,
+        })
+    }
+
+    /// Applies the changes to the filesystem, returning the placement of every move and extraction
+    /// (in that order). Conflicts are resolved as usual unless the placements which were `planned`
+    /// are provided, in which case they are replayed exactly.
+    /// Audits only extract videos virtually, relying on `conflicts` to simulate everything else.
+    fn apply(
+        &self,
+        config: &config::Config,
+        audit: bool,
+        conflicts: &conflict_manager::ConflictManager,
+        planned: Option<&[conflict_manager::Placement]>,
+    ) -> Result<Vec<conflict_manager::Placement>, errors::Error> {
+        let command = config.import_mode.command();
+
+        // Sources are only ever removed when they are being moved into the library.
+        let discards = match config.import_mode {
+            config::ImportMode::Move => self.discards.as_slice(),
+            _ => &[],
+        };
+
+        for (_, target) in self.moves.iter() {
+            conflicts
+                .create_dir_all(target.parent().unwrap())
+                .wrap_user_err(
+                format!(
+                    "Unable to create directory '{}'.",
+                    target.parent().unwrap().display()
+                ),
+                &["Make sure that you've got permission to create this directory and try again."],
+            )?;
+        }
+
+        // Extracted videos are written to a temporary file alongside their target so that
+        // they can take part in the same conflict resolution as the rest of the group.
+        let mut moves = self.moves.clone();
+        for (source, video, target) in self.extractions.iter() {
+            let temp = conflict_manager::temporary_path(target);
+
+            if audit {
+                conflicts.simulate_create(&temp);
+            } else {
+                motion_photos::extract(source, video, &temp).wrap_user_err(
+                    format!("Failed to extract the video embedded in '{}'", source.display()),
+                    &["Make sure that you have permission to write to the target directory and try again."],
+                )?;
+            }
+
+            moves.push((temp, target.clone()));
+        }
+
+        let conflicts = conflicts.clone().with_known_hashes(&self.hashes);
+        let (source, target) = &moves[0];
+        let placements = match planned {
+            Some(planned) => conflicts.replay(&moves, planned),
+            None => conflicts.rename_no_conflict(&moves),
+        };
+        if placements.is_err() {
+            for (temp, _) in moves.iter().skip(self.moves.len()) {
+                let _ = std::fs::remove_file(temp);
+            }
+        }
+
+        let placements = placements.wrap_user_err(
+            format!(
+                "Failed to move '{}' to '{}'",
+                source.display(),
+                target.display()
+            ),
+            &["Make sure that you have permission to move the image and try again."],
+        )?;
+
+        for (i, placement) in placements.iter().enumerate() {
+            let (source, command) = match self.moves.get(i) {
+                Some((source, _)) => (source, command),
+                None => (&self.extractions[i - self.moves.len()].0, "extract"),
+            };
+
+            match placement {
+                conflict_manager::Placement::Moved(path)
+                | conflict_manager::Placement::Replaced { path, .. }
+                | conflict_manager::Placement::Quarantined(path) => {
+                    info!("{} '{}' '{}'", command, source.display(), path.display())
+                }
+                conflict_manager::Placement::Removed(_) if command != "extract" => {
+                    info!("rm '{}'", source.display())
+                }
+                conflict_manager::Placement::Removed(_) => {}
+                conflict_manager::Placement::Skipped => {
+                    // Videos which were extracted for a skipped image are not left lying around in the library.
+                    if command == "extract" {
+                        let _ = std::fs::remove_file(&moves[i].0);
+                    }
+                }
+            }
+        }
+
+        for discard in discards.iter() {
+            conflicts.remove(discard).wrap_user_err(
+                format!("Failed to remove '{}'", discard.display()),
+                &["Make sure that you have permission to remove the image and try again."],
+            )?;
+
+            info!("rm '{}'", discard.display());
+        }
+
+        Ok(placements)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a minimal JPEG whose EXIF data records when it was taken, followed by `extra` bytes.
+    fn jpeg(date_time: &str, extra: &[u8]) -> Vec<u8> {
+        let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+        tiff.extend([0, 1, 0x87, 0x69, 0, 4, 0, 0, 0, 1, 0, 0, 0, 26, 0, 0, 0, 0]);
+        tiff.extend([0, 1, 0x90, 0x03, 0, 2, 0, 0, 0, 20, 0, 0, 0, 44, 0, 0, 0, 0]);
+        tiff.extend(date_time.as_bytes());
+        tiff.push(0);
+
+        let app1 = [b"Exif\0\0".as_slice(), &tiff].concat();
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        data.extend((app1.len() as u16 + 2).to_be_bytes());
+        data.extend(app1);
+        data.extend([0xFF, 0xD9]);
+        data.extend(extra);
+        data
+    }
+
+    fn plan_conflicting_imports(dir: &Path) -> (config::Config, plan::Plan) {
+        let mut config = config::Config {
+            source: dir.join("src"),
+            target: dir.join("lib"),
+            template: "{year}/{date}".to_string(),
+            ..Default::default()
+        };
+        config.removal.method = config::RemovalMethod::Delete;
+        config.conflict.identical = config::ConflictStrategy::Delete;
+
+        std::fs::create_dir_all(dir.join("src/c")).unwrap();
+        std::fs::write(dir.join("src/a.JPG"), jpeg("2024:05:06 10:00:00", b"")).unwrap();
+        std::fs::write(dir.join("src/b.JPG"), jpeg("2024:05:06 10:00:00", b"b")).unwrap();
+        std::fs::write(
+            dir.join("src/c/IMG_0001.JPG"),
+            jpeg("2024:05:06 10:00:00", b""),
+        )
+        .unwrap();
+
+        let quarantine =
+            quarantine::Quarantine::new(&config, template_context(&config.quarantine.template));
+        let mut plan = plan::Plan::default();
+        let files = vec![
+            dir.join("src/a.JPG"),
+            dir.join("src/b.JPG"),
+            dir.join("src/c/IMG_0001.JPG"),
+        ];
+        let context = Context::open(&config, true).unwrap();
+        let mut indexes = Indexes::open(&config, &quarantine, &context).unwrap();
+        import_images(
+            &config,
+            &quarantine,
+            &context,
+            &mut indexes,
+            Some(&mut plan),
+            files,
+        )
+        .unwrap();
+        plan.save(dir.join("plan.json")).unwrap();
+
+        (config, plan)
+    }
+
+    #[test]
+    fn test_plan_records_resolved_conflicts() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (config, plan) = plan_conflicting_imports(dir);
+
+        let destination = dir.join("lib/2024/2024-05-06.JPG");
+        let actions: Vec<&plan::PlannedAction> = plan
+            .imports
+            .iter()
+            .flat_map(|import| import.files.iter().map(|file| &file.action))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                &plan::PlannedAction::Import {
+                    destination: destination.clone()
+                },
+                &plan::PlannedAction::Import {
+                    destination: dir.join("lib/2024/2024-05-06 (1).JPG")
+                },
+                &plan::PlannedAction::Discard {
+                    duplicate: Some(plan::Existing {
+                        path: destination.clone(),
+                        hash: conflict_manager::file_hash(dir.join("src/a.JPG")).unwrap(),
+                    })
+                },
+            ]
+        );
+        assert!(!dir.join("lib").exists());
+
+        let quarantine =
+            quarantine::Quarantine::new(&config, template_context(&config.quarantine.template));
+        apply_plan(&config, &quarantine, &dir.join("plan.json"), false).unwrap();
+
+        assert_eq!(
+            std::fs::read(&destination).unwrap(),
+            jpeg("2024:05:06 10:00:00", b"")
+        );
+        assert_eq!(
+            std::fs::read(dir.join("lib/2024/2024-05-06 (1).JPG")).unwrap(),
+            jpeg("2024:05:06 10:00:00", b"b")
+        );
+        assert!(!dir.join("src/a.JPG").exists());
+        assert!(!dir.join("src/b.JPG").exists());
+        assert!(!dir.join("src/c/IMG_0001.JPG").exists());
+    }
+
+    #[test]
+    fn test_apply_skips_changed_destinations() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (config, _) = plan_conflicting_imports(dir);
+
+        // Something else takes the planned destination before the plan is applied.
+        let destination = dir.join("lib/2024/2024-05-06.JPG");
+        std::fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        std::fs::write(&destination, "other").unwrap();
+
+        let quarantine =
+            quarantine::Quarantine::new(&config, template_context(&config.quarantine.template));
+        apply_plan(&config, &quarantine, &dir.join("plan.json"), false).unwrap();
+
+        assert_eq!(std::fs::read(&destination).unwrap(), b"other");
+        assert!(dir.join("src/a.JPG").exists());
+        assert!(dir.join("lib/2024/2024-05-06 (1).JPG").exists());
+        assert!(dir.join("src/c/IMG_0001.JPG").exists());
+    }
+
+    #[test]
+    fn test_apply_updates_library_index() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (mut config, _) = plan_conflicting_imports(dir);
+        config.index.enabled = true;
+
+        let quarantine =
+            quarantine::Quarantine::new(&config, template_context(&config.quarantine.template));
+        apply_plan(&config, &quarantine, &dir.join("plan.json"), false).unwrap();
+
+        // The library was empty when the index was opened, so only the applied files were recorded.
+        let records = store::Store::new(&config.target, "index").load(4).unwrap();
+        let mut paths: Vec<&str> = records.iter().map(|record| record[3].as_str()).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec!["2024/2024-05-06 (1).JPG", "2024/2024-05-06.JPG"]
+        );
+    }
+
+    #[test]
+    fn test_takeout_sidecar_is_claimed_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        // Copies leave the sidecar in place, where it would otherwise be found again.
+        let config = config::Config {
+            source: dir.join("src"),
+            target: dir.join("lib"),
+            template: "{year}/{date}".to_string(),
+            import_mode: config::ImportMode::Copy,
+            ..Default::default()
+        };
+
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join("src/IMG_0001.jpg"),
+            jpeg("2024:05:06 10:00:00", b""),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("src/IMG_0001-edited.jpg"),
+            jpeg("2024:05:06 10:00:00", b"edited"),
+        )
+        .unwrap();
+        std::fs::write(dir.join("src/IMG_0001.jpg.json"), "{}").unwrap();
+
+        let quarantine =
+            quarantine::Quarantine::new(&config, template_context(&config.quarantine.template));
+        let files = vec![
+            dir.join("src/IMG_0001-edited.jpg"),
+            dir.join("src/IMG_0001.jpg"),
+        ];
+        let context = Context::open(&config, false).unwrap();
+        let mut indexes = Indexes::open(&config, &quarantine, &context).unwrap();
+        import_images(&config, &quarantine, &context, &mut indexes, None, files).unwrap();
+
+        assert!(dir.join("lib/2024/2024-05-06.jpg.json").exists());
+        assert!(dir.join("lib/2024/2024-05-06 (1).jpg").exists());
+        assert!(!dir.join("lib/2024/2024-05-06 (1).jpg.json").exists());
+    }
+
+    #[test]
+    fn test_shared_sidecar_is_claimed_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        // Copies leave the sidecar in place, where it would otherwise be found again.
+        let config = config::Config {
+            source: dir.join("src"),
+            target: dir.join("lib"),
+            template: "{year}/{date}".to_string(),
+            import_mode: config::ImportMode::Copy,
+            ..Default::default()
+        };
+
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join("src/IMG_0001.jpg"),
+            jpeg("2024:05:06 10:00:00", b""),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("src/IMG_0001.jpeg"),
+            jpeg("2024:05:07 10:00:00", b""),
+        )
+        .unwrap();
+        std::fs::write(dir.join("src/IMG_0001.xmp"), "<xmp />").unwrap();
+
+        let quarantine =
+            quarantine::Quarantine::new(&config, template_context(&config.quarantine.template));
+        let files = vec![dir.join("src/IMG_0001.jpeg"), dir.join("src/IMG_0001.jpg")];
+        let context = Context::open(&config, false).unwrap();
+        let mut indexes = Indexes::open(&config, &quarantine, &context).unwrap();
+        import_images(&config, &quarantine, &context, &mut indexes, None, files).unwrap();
+
+        assert!(dir.join("lib/2024/2024-05-07.xmp").exists());
+        assert!(dir.join("lib/2024/2024-05-06.jpg").exists());
+        assert!(!dir.join("lib/2024/2024-05-06.xmp").exists());
+    }
+}
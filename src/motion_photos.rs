@@ -0,0 +1,336 @@
+//! Support for keeping the still and motion halves of Apple Live Photos and Google
+//! Motion Photos together when they are imported.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::{image::Metadata, isobmff, pairs::SiblingIndex};
+
+/// The QuickTime metadata key under which Apple stores a Live Photo's content identifier.
+const CONTENT_IDENTIFIER_KEY: &[u8] = b"com.apple.quicktime.content.identifier";
+
+/// The number of bytes at the start of a file which are searched for an XMP packet.
+const XMP_SEARCH_LENGTH: u64 = 256 * 1024;
+
+/// An index of the Live Photo videos being imported and the content identifiers they carry.
+#[derive(Default)]
+pub struct VideoIndex {
+    videos: HashMap<PathBuf, Option<String>>,
+    by_identifier: HashMap<String, Vec<PathBuf>>,
+}
+
+impl VideoIndex {
+    pub fn new<'a, I: IntoIterator<Item = &'a PathBuf>>(files: I) -> Self {
+        let mut index = VideoIndex::default();
+        for file in files.into_iter().filter(|f| is_live_photo_video(f)) {
+            let identifier = content_identifier(file).ok().flatten();
+            if let Some(identifier) = identifier.as_ref() {
+                index
+                    .by_identifier
+                    .entry(identifier.clone())
+                    .or_default()
+                    .push(file.clone());
+            }
+
+            index.videos.insert(file.clone(), identifier);
+        }
+
+        index
+    }
+
+    /// Finds the video half of a Live Photo, preferring a video with the same content identifier
+    /// and falling back to one which shares the still's name (as long as their identifiers agree).
+    /// Videos which have already been `handled` (imported with another still) are never returned.
+    ///
+    /// When several videos share the still's content identifier, the one which shares its name is
+    /// preferred, followed by the first by path, so that the choice never depends on the scan order.
+    pub fn find(
+        &self,
+        still: &Metadata,
+        siblings: &SiblingIndex,
+        handled: &HashSet<PathBuf>,
+    ) -> Option<PathBuf> {
+        let stem = still.path.with_extension("");
+        if let Some(video) = still
+            .content_identifier
+            .as_ref()
+            .and_then(|id| self.by_identifier.get(id))
+            .and_then(|videos| {
+                videos
+                    .iter()
+                    .filter(|video| !handled.contains(*video))
+                    .min_by_key(|video| (video.with_extension("") != stem, *video))
+            })
+        {
+            return Some(video.clone());
+        }
+
+        siblings
+            .siblings(still.path)
+            .filter(|sibling| !handled.contains(*sibling))
+            .find(
+                |sibling| match (self.videos.get(*sibling), &still.content_identifier) {
+                    (Some(Some(a)), Some(b)) => a == b,
+                    (Some(_), _) => true,
+                    (None, _) => false,
+                },
+            )
+            .cloned()
+    }
+}
+
+fn is_live_photo_video<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mov"))
+}
+
+/// Reads the Live Photo content identifier from the `moov/meta` atom of a QuickTime video.
+pub fn content_identifier<P: AsRef<Path>>(path: P) -> std::io::Result<Option<String>> {
+    let Some(moov) = isobmff::read_top_level(path, b"moov")? else {
+        return Ok(None);
+    };
+
+    let Some(meta) = isobmff::atoms(&moov).find(|atom| &atom.kind == b"meta") else {
+        return Ok(None);
+    };
+
+    // QuickTime's `meta` atom is a plain container, while the ISO variant is a "full" atom.
+    let children = if meta.data.get(4..8) == Some(b"hdlr") {
+        meta.children()
+    } else {
+        meta.full_children()
+    };
+
+    let (mut keys, mut items) = (None, None);
+    for atom in children {
+        match &atom.kind {
+            b"keys" => keys = Some(atom),
+            b"ilst" => items = Some(atom),
+            _ => {}
+        }
+    }
+
+    let (Some(keys), Some(items)) = (keys, items) else {
+        return Ok(None);
+    };
+
+    // Keys are listed with 1-based indices, which are then used as the atom types in the item list.
+    let Some(index) = isobmff::atoms(keys.data.get(8..).unwrap_or_default())
+        .position(|key| key.data == CONTENT_IDENTIFIER_KEY)
+        .map(|i| (i + 1) as u32)
+    else {
+        return Ok(None);
+    };
+
+    Ok(items
+        .children()
+        .find(|item| u32::from_be_bytes(item.kind) == index)
+        .and_then(|item| item.find(b"data"))
+        .and_then(|data| data.data.get(8..))
+        .map(|value| String::from_utf8_lossy(value).trim().to_string()))
+}
+
+/// The location of a video embedded at the end of a Google Motion Photo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmbeddedVideo {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Locates the video embedded within a Google Motion Photo using the offsets advertised in its XMP metadata.
+pub fn embedded_video<P: AsRef<Path>>(path: P) -> std::io::Result<Option<EmbeddedVideo>> {
+    let mut file = std::fs::File::open(path)?;
+    let file_length = file.metadata()?.len();
+
+    let mut header = Vec::new();
+    file.by_ref()
+        .take(XMP_SEARCH_LENGTH)
+        .read_to_end(&mut header)?;
+
+    let Some(length) = find_xmp(&header).and_then(video_length) else {
+        return Ok(None);
+    };
+
+    if length < 8 || length >= file_length {
+        return Ok(None);
+    }
+
+    let offset = file_length - length;
+    let mut atom = [0u8; 8];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut atom)?;
+
+    if &atom[4..8] != b"ftyp" {
+        return Ok(None);
+    }
+
+    Ok(Some(EmbeddedVideo { offset, length }))
+}
+
+/// Writes the embedded video from a Motion Photo out to its own file.
+pub fn extract<S: AsRef<Path>, D: AsRef<Path>>(
+    source: S,
+    video: &EmbeddedVideo,
+    dest: D,
+) -> std::io::Result<()> {
+    let mut file = std::fs::File::open(source)?;
+    file.seek(SeekFrom::Start(video.offset))?;
+
+    let mut output = std::fs::File::create(dest)?;
+    std::io::copy(&mut file.take(video.length), &mut output)?;
+    output.sync_all()
+}
+
+fn find_xmp(data: &[u8]) -> Option<&str> {
+    let start = find(data, b"<x:xmpmeta")?;
+    let end = find(&data[start..], b"</x:xmpmeta>")? + start;
+    std::str::from_utf8(&data[start..end]).ok()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Determines the length of the trailing video from either the legacy `MicroVideoOffset`
+/// attribute or the `MotionPhoto` item of the newer container directory.
+fn video_length(xmp: &str) -> Option<u64> {
+    if let Some(offset) = attribute(xmp, "MicroVideoOffset") {
+        return offset.parse().ok();
+    }
+
+    xmp.split("<Container:Item")
+        .skip(1)
+        .map(|item| &item[..item.find('>').unwrap_or(item.len())])
+        .find(|item| attribute(item, "Semantic") == Some("MotionPhoto"))
+        .and_then(|item| attribute(item, "Length"))
+        .and_then(|length| length.parse().ok())
+}
+
+/// Finds the value of an XML attribute by its local name, ignoring its namespace prefix.
+fn attribute<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!(":{}=\"", name);
+    let start = xml.find(&pattern)? + pattern.len();
+    let end = xml[start..].find('"')? + start;
+    Some(&xml[start..end])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn atom(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        [&(data.len() as u32 + 8).to_be_bytes()[..], kind, data].concat()
+    }
+
+    /// Builds a QuickTime video whose `moov/meta` atom carries the provided content identifier.
+    fn live_photo_video(identifier: &str) -> Vec<u8> {
+        let key = atom(b"mdta", CONTENT_IDENTIFIER_KEY);
+        let keys = atom(b"keys", &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &key].concat());
+        let value = atom(
+            b"data",
+            &[&[0, 0, 0, 1, 0, 0, 0, 0][..], identifier.as_bytes()].concat(),
+        );
+        let items = atom(b"ilst", &atom(&1u32.to_be_bytes(), &value));
+        let meta = atom(b"meta", &[atom(b"hdlr", &[0; 24]), keys, items].concat());
+
+        [atom(b"ftyp", b"qt  "), atom(b"moov", &meta)].concat()
+    }
+
+    #[test]
+    fn test_content_identifier() {
+        let temp = tempfile::tempdir().unwrap();
+        let video = temp.path().join("IMG_0001.MOV");
+        std::fs::write(&video, live_photo_video("ABC-123")).unwrap();
+        assert_eq!(
+            content_identifier(&video).unwrap().as_deref(),
+            Some("ABC-123")
+        );
+
+        std::fs::write(&video, atom(b"ftyp", b"qt  ")).unwrap();
+        assert_eq!(content_identifier(&video).unwrap(), None);
+    }
+
+    #[test]
+    fn test_videos_are_only_claimed_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = ["IMG_0001.JPG", "IMG_0001.JPEG", "IMG_0001.MOV"]
+            .iter()
+            .map(|name| temp.path().join(name))
+            .collect();
+        for file in files.iter() {
+            std::fs::write(file, file.to_string_lossy().as_bytes()).unwrap();
+        }
+
+        let siblings = SiblingIndex::new(&files);
+        let videos = VideoIndex::new(&files);
+        let mut handled = HashSet::new();
+
+        let video = videos.find(&Metadata::new(&files[0]), &siblings, &handled);
+        assert_eq!(video.as_ref(), Some(&files[2]));
+        handled.extend(video);
+
+        assert_eq!(
+            videos.find(&Metadata::new(&files[1]), &siblings, &handled),
+            None
+        );
+    }
+
+    #[test]
+    fn test_shared_identifiers_prefer_matching_names() {
+        let temp = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = ["IMG_0002.MOV", "IMG_0001.JPG", "IMG_0001.MOV"]
+            .iter()
+            .map(|name| temp.path().join(name))
+            .collect();
+        std::fs::write(&files[0], live_photo_video("ABC-123")).unwrap();
+        std::fs::write(&files[2], live_photo_video("ABC-123")).unwrap();
+
+        let siblings = SiblingIndex::new(&files);
+        let videos = VideoIndex::new(&files);
+        let mut still = Metadata::new(&files[1]);
+        still.content_identifier = Some("ABC-123".to_string());
+
+        let mut handled = HashSet::new();
+        assert_eq!(
+            videos.find(&still, &siblings, &handled).as_ref(),
+            Some(&files[2])
+        );
+
+        handled.insert(files[2].clone());
+        assert_eq!(
+            videos.find(&still, &siblings, &handled).as_ref(),
+            Some(&files[0])
+        );
+    }
+
+    #[test]
+    fn test_video_length() {
+        assert_eq!(
+            video_length(
+                r#"<rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="4242"/>"#
+            ),
+            Some(4242)
+        );
+
+        assert_eq!(
+            video_length(
+                r#"<Container:Directory><rdf:Seq>
+                <rdf:li><Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary" Item:Length="0"/></rdf:li>
+                <rdf:li><Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="1337"/></rdf:li>
+                </rdf:Seq></Container:Directory>"#
+            ),
+            Some(1337)
+        );
+
+        assert_eq!(
+            video_length(r#"<rdf:Description tiff:Make="Canon"/>"#),
+            None
+        );
+    }
+}
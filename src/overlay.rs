@@ -0,0 +1,81 @@
+//! A virtual overlay of the filesystem, which records the changes a run would make without
+//! making them so that audit mode can follow exactly the same decisions as a real run.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Default)]
+pub struct Overlay {
+    /// The paths which have been changed, mapped to the real file which holds the content they
+    /// would have (or `None` if they would have been removed).
+    entries: Mutex<HashMap<PathBuf, Option<PathBuf>>>,
+}
+
+impl Overlay {
+    /// Whether a file would exist at `path`.
+    pub fn exists(&self, path: &Path) -> bool {
+        self.content(path).is_some()
+    }
+
+    /// The real file which holds the content that `path` would have, if it would exist.
+    pub fn content(&self, path: &Path) -> Option<PathBuf> {
+        match self.entries.lock().unwrap().get(path) {
+            Some(content) => content.clone(),
+            None if path.exists() => Some(path.to_path_buf()),
+            None => None,
+        }
+    }
+
+    /// Records that `target` would be given the content of `source`, which is removed if `consume` is set.
+    pub fn copy(&self, source: &Path, target: &Path, consume: bool) {
+        let content = self.content(source);
+        let mut entries = self.entries.lock().unwrap();
+        if consume {
+            entries.insert(source.to_path_buf(), None);
+        }
+
+        entries.insert(target.to_path_buf(), content);
+    }
+
+    /// Records that a new file would be created at `path`, whose content is not yet known.
+    pub fn create(&self, path: &Path) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Some(path.to_path_buf()));
+    }
+
+    /// Records that the file at `path` would be removed.
+    pub fn remove(&self, path: &Path) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overlay() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("a.jpg"), "a").unwrap();
+
+        let overlay = Overlay::default();
+        overlay.copy(&dir.join("a.jpg"), &dir.join("b.jpg"), true);
+        assert!(!overlay.exists(&dir.join("a.jpg")));
+        assert_eq!(overlay.content(&dir.join("b.jpg")), Some(dir.join("a.jpg")));
+
+        overlay.copy(&dir.join("b.jpg"), &dir.join("c.jpg"), false);
+        overlay.remove(&dir.join("b.jpg"));
+        assert!(!overlay.exists(&dir.join("b.jpg")));
+        assert_eq!(overlay.content(&dir.join("c.jpg")), Some(dir.join("a.jpg")));
+        assert!(dir.join("a.jpg").exists());
+    }
+}
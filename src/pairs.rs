@@ -0,0 +1,57 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::image::Metadata;
+
+const JPEG_EXTENSIONS: [&str; 2] = ["JPG", "JPEG"];
+
+/// The role an image plays within a RAW+JPEG pair, exposed to templates as `{pair.role}`.
+pub const ROLE_RAW: &str = "raw";
+pub const ROLE_JPEG: &str = "jpeg";
+
+/// Determines whether the provided file is a JPEG which could form half of a RAW+JPEG pair.
+pub fn is_jpeg<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_uppercase())
+        .is_some_and(|ext| JPEG_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// An index of the files being imported, keyed by their directory and file stem,
+/// which is how cameras name the two halves of a RAW+JPEG pair.
+#[derive(Default)]
+pub struct SiblingIndex {
+    files: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl SiblingIndex {
+    pub fn new<'a, I: IntoIterator<Item = &'a PathBuf>>(files: I) -> Self {
+        let mut index = SiblingIndex::default();
+        for file in files {
+            index
+                .files
+                .entry(file.with_extension(""))
+                .or_default()
+                .push(file.clone());
+        }
+
+        index
+    }
+
+    /// Finds the files which share a directory and file stem with `path`.
+    pub fn siblings<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a PathBuf> {
+        self.files
+            .get(&path.with_extension(""))
+            .into_iter()
+            .flatten()
+            .filter(move |sibling| sibling.as_path() != path)
+    }
+}
+
+/// Checks whether two images were captured at the same moment, which distinguishes a genuine
+/// RAW+JPEG pair from two unrelated images which happen to share a name.
+pub fn is_same_capture(raw: &Metadata, jpeg: &Metadata) -> bool {
+    matches!((&raw.date_time, &jpeg.date_time), (Some(a), Some(b)) if a.trim() == b.trim())
+}
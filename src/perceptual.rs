@@ -0,0 +1,140 @@
+//! Perceptual hashing of image content, which allows near-duplicates (the same photo re-saved,
+//! stripped of its metadata or downsized) to be detected even though their bytes differ, along
+//! with a persistent index of the perceptual hashes of every image in the target library.
+
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+
+use crate::file_index::{FileIndex, Value};
+
+const INDEX_FILE: &str = "perceptual";
+
+/// Computes the difference hash (dHash) of an image: the image is reduced to a 9x8 grayscale
+/// thumbnail and each bit records whether a pixel is brighter than its right-hand neighbour.
+///
+/// JPEG and PNG images are decoded directly, while RAW images use their embedded preview.
+/// Returns `None` for files which cannot be decoded.
+pub fn dhash(path: &Path) -> Option<u64> {
+    let image = if crate::image::is_raw(path) {
+        rawler::analyze::extract_preview_pixels(path, &Default::default()).ok()?
+    } else {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        if !matches!(extension.as_str(), "jpg" | "jpeg" | "png") {
+            return None;
+        }
+
+        image::ImageReader::open(path)
+            .ok()?
+            .with_guessed_format()
+            .ok()?
+            .decode()
+            .ok()?
+    };
+
+    let thumbnail = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    Some(hash)
+}
+
+/// The number of bits which differ between two perceptual hashes.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Perceptual hashes are stored as 16 hexadecimal digits.
+impl Value for u64 {
+    fn format(&self) -> String {
+        format!("{:016x}", self)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        u64::from_str_radix(value, 16).ok()
+    }
+}
+
+pub struct PerceptualIndex {
+    files: FileIndex<u64>,
+}
+
+impl PerceptualIndex {
+    /// Opens the perceptual index for the library at `root`, hashing any images which have been
+    /// added or modified since it was last updated (on a pool of `threads` workers) and excluding
+    /// files for which `ignore` returns true.
+    pub fn open<P: AsRef<Path>, F: Fn(&Path) -> bool>(
+        root: P,
+        threads: usize,
+        ignore: F,
+    ) -> std::io::Result<Self> {
+        Ok(PerceptualIndex {
+            files: FileIndex::open(root, INDEX_FILE, threads, ignore, dhash)?,
+        })
+    }
+
+    /// Finds the image in the library which is most similar to the provided perceptual hash,
+    /// as long as it is within `max_distance`, ignoring the file at `exclude`. The image may have
+    /// been removed since it was indexed, so callers should check that it still exists.
+    pub fn find_similar<P: AsRef<Path>>(
+        &self,
+        hash: u64,
+        max_distance: u32,
+        exclude: P,
+    ) -> Option<(PathBuf, u32)> {
+        let exclude = exclude.as_ref().strip_prefix(self.files.root()).ok();
+
+        self.files
+            .iter()
+            .map(|(path, value)| (path, distance(hash, *value)))
+            .filter(|(path, distance)| *distance <= max_distance && Some(*path) != exclude)
+            .min_by(|(a, da), (b, db)| da.cmp(db).then_with(|| a.cmp(b)))
+            .map(|(path, distance)| (self.files.root().join(path), distance))
+    }
+
+    /// Records an image which has been added to the library, persisting it immediately.
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, hash: u64) -> std::io::Result<()> {
+        self.files.insert(path, || Ok(hash)).map(|_| ())
+    }
+
+    /// Records an image which an audit would have added to the library, keeping it in memory only.
+    pub fn simulate_insert<P: AsRef<Path>>(&mut self, path: P, hash: u64) {
+        self.files.simulate_insert(path, hash);
+    }
+
+    /// Persists the refreshed index, dropping any images which have been removed from the library.
+    pub fn save(&self) -> std::io::Result<()> {
+        self.files.save()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dhash_survives_resizing() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let image = image::RgbImage::from_fn(256, 192, |x, y| {
+            image::Rgb([(x ^ y) as u8, (x * 3) as u8, (y * 2) as u8])
+        });
+        image.save(dir.join("original.png")).unwrap();
+        image::imageops::resize(&image, 128, 96, FilterType::Triangle)
+            .save(dir.join("small.jpg"))
+            .unwrap();
+
+        let original = dhash(&dir.join("original.png")).unwrap();
+        let small = dhash(&dir.join("small.jpg")).unwrap();
+        assert!(distance(original, small) <= 6);
+    }
+}
@@ -0,0 +1,118 @@
+//! A pool of workers which prepare files (reading their metadata and hashing them) ahead of the
+//! single thread which decides where each file goes, so that slow storage is kept busy without
+//! making those decisions depend on the number of threads.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+};
+
+/// The number of worker threads to use when `configured` is zero, which is one per CPU core.
+pub fn threads(configured: usize) -> usize {
+    match configured {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+/// Prepares each item on a pool of `threads` workers and passes the results to `consume` on the
+/// calling thread, always in the same order as `items` regardless of which worker finishes first.
+///
+/// Workers stop picking up new items as soon as `consume` returns an error, which is then returned.
+pub fn ordered<'a, T, R, E, P, C>(
+    items: &'a [T],
+    threads: usize,
+    prepare: P,
+    mut consume: C,
+) -> Result<(), E>
+where
+    T: Sync,
+    R: Send,
+    P: Fn(&'a T) -> R + Sync,
+    C: FnMut(&'a T, R) -> Result<(), E>,
+{
+    if threads <= 1 {
+        return items
+            .iter()
+            .try_for_each(|item| consume(item, prepare(item)));
+    }
+
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+
+    std::thread::scope(|scope| {
+        // Workers wait for the consumer once they get far enough ahead, rather than holding the
+        // prepared results (and whatever they have read) for every remaining item in memory.
+        let (sender, receiver) = mpsc::sync_channel(threads * 2);
+        for _ in 0..threads.min(items.len()) {
+            let sender = sender.clone();
+            let (next, stopped, prepare) = (&next, &stopped, &prepare);
+            scope.spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+
+                    if sender.send((i, prepare(item))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Results which arrive early wait here until every item before them has been consumed.
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (i, result) in receiver.iter() {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&expected) {
+                if let Err(e) = consume(&items[expected], result) {
+                    stopped.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+
+                expected += 1;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ordered() {
+        let items: Vec<u64> = (0..100).collect();
+        for threads in [1, 4] {
+            let mut consumed = Vec::new();
+            ordered(
+                &items,
+                threads,
+                |n| {
+                    std::thread::sleep(std::time::Duration::from_micros((100 - n) * 10));
+                    n * 2
+                },
+                |n, doubled| {
+                    consumed.push((*n, doubled));
+                    if *n == 50 {
+                        Err(())
+                    } else {
+                        Ok(())
+                    }
+                },
+            )
+            .unwrap_err();
+
+            let expected: Vec<(u64, u64)> = (0..=50).map(|n| (n, n * 2)).collect();
+            assert_eq!(consumed, expected);
+        }
+    }
+}
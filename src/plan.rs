@@ -0,0 +1,319 @@
+//! Machine-readable plans describing every change an import would make, which can be reviewed
+//! (or edited) before being applied with `imgsort apply`.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conflict_manager::ConflictManager,
+    hash_cache::file_info,
+    image::{self, Metadata},
+};
+
+/// The version of the plan format, which is bumped whenever it changes incompatibly.
+pub const VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub imports: Vec<PlannedImport>,
+}
+
+/// A group of files (an image along with its companions) which are imported together.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlannedImport {
+    /// Why the files are being imported to (or removed from) their destinations.
+    pub reason: String,
+    /// The metadata of the primary image, as it was when the plan was created.
+    #[serde(default)]
+    pub metadata: Snapshot,
+    pub files: Vec<PlannedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedFile {
+    #[serde(flatten)]
+    pub action: PlannedAction,
+    pub source: PathBuf,
+    pub size: u64,
+    pub modified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// The video embedded within the source, which is extracted and placed instead of the source itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video: Option<PlannedVideo>,
+}
+
+/// Where a file ends up, once any conflicts with the files already in the library have been resolved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum PlannedAction {
+    /// The file is imported to `destination` (which may carry a conflict suffix) using the
+    /// configured import mode, as long as nothing has been placed there since.
+    Import { destination: PathBuf },
+    /// The file replaces the one at `destination`, as long as its content still has the hash
+    /// `existing`. The replaced file is moved to `quarantine` if set, and removed otherwise.
+    Replace {
+        destination: PathBuf,
+        existing: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quarantine: Option<PathBuf>,
+    },
+    /// The file is moved into quarantine at `destination`.
+    Quarantine { destination: PathBuf },
+    /// The file is removed, either because it's identical to the `duplicate` already in the
+    /// library (which must still be there) or because it isn't wanted.
+    Discard {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duplicate: Option<Existing>,
+    },
+    /// The file is left where it is.
+    Skip,
+}
+
+/// A file in the library, along with the hash of the content it had when the plan was created.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Existing {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// The location of a video embedded within an image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlannedVideo {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// The metadata which determined where an image is imported to.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lens_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl From<&Metadata<'_>> for Snapshot {
+    fn from(metadata: &Metadata<'_>) -> Self {
+        Snapshot {
+            date_time: metadata.date_time.clone(),
+            camera_make: metadata.camera_make.clone(),
+            camera_model: metadata.camera_model.clone(),
+            lens_model: metadata.lens_model.clone(),
+            latitude: metadata.latitude,
+            longitude: metadata.longitude,
+            description: metadata.description.clone(),
+        }
+    }
+}
+
+impl PlannedImport {
+    /// Describes every way in which the files, their metadata or their destinations have changed
+    /// since the plan was created, any of which mean that the import can no longer be applied as planned.
+    pub fn changes(&self, conflicts: &ConflictManager) -> Vec<String> {
+        let mut changes: Vec<String> = self
+            .files
+            .iter()
+            .filter_map(|file| file.validate(conflicts).err())
+            .collect();
+
+        let primary = self
+            .files
+            .first()
+            .filter(|_| self.metadata != Snapshot::default());
+        if let (true, Some(primary)) = (changes.is_empty(), primary) {
+            match image::load(&primary.source) {
+                Some(Ok(metadata)) if Snapshot::from(&metadata) == self.metadata => {}
+                _ => changes.push(format!(
+                    "the metadata of '{}' has changed since the plan was created",
+                    primary.source.display()
+                )),
+            }
+        }
+
+        changes
+    }
+}
+
+impl PlannedFile {
+    /// Describes a file as it currently is on disk.
+    pub fn new(
+        source: &Path,
+        action: PlannedAction,
+        hash: Option<String>,
+    ) -> std::io::Result<Self> {
+        let (size, modified) = file_info(source)?;
+        Ok(PlannedFile {
+            action,
+            source: source.to_path_buf(),
+            size,
+            modified,
+            hash,
+            video: None,
+        })
+    }
+
+    /// Describes the video embedded within a file as it currently is on disk.
+    pub fn video(
+        source: &Path,
+        action: PlannedAction,
+        video: PlannedVideo,
+    ) -> std::io::Result<Self> {
+        Ok(PlannedFile {
+            video: Some(video),
+            ..PlannedFile::new(source, action, None)?
+        })
+    }
+
+    /// The path at which the file is placed, if it is placed anywhere.
+    pub fn destination(&self) -> Option<&Path> {
+        match &self.action {
+            PlannedAction::Import { destination }
+            | PlannedAction::Replace { destination, .. }
+            | PlannedAction::Quarantine { destination } => Some(destination),
+            PlannedAction::Discard { .. } | PlannedAction::Skip => None,
+        }
+    }
+
+    /// Checks that neither the file nor the library files it was placed against have changed since
+    /// the plan was created, taking any changes simulated by `conflicts` into account.
+    pub fn validate(&self, conflicts: &ConflictManager) -> Result<(), String> {
+        match file_info(&self.source) {
+            Ok((size, modified)) if size == self.size && modified == self.modified => {}
+            Ok(_) => {
+                return Err(format!(
+                    "'{}' has changed since the plan was created",
+                    self.source.display()
+                ))
+            }
+            Err(e) => {
+                return Err(format!(
+                    "'{}' is no longer available: {}",
+                    self.source.display(),
+                    e
+                ))
+            }
+        }
+
+        let unchanged = |path: &Path, hash: &str| {
+            if conflicts.hash(path).is_ok_and(|current| current == hash) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "'{}' has changed since the plan was created",
+                    path.display()
+                ))
+            }
+        };
+
+        match &self.action {
+            PlannedAction::Import { destination } | PlannedAction::Quarantine { destination }
+                if conflicts.exists(destination) =>
+            {
+                Err(format!(
+                    "'{}' has been created since the plan was created",
+                    destination.display()
+                ))
+            }
+            PlannedAction::Replace {
+                destination,
+                existing,
+                ..
+            } => unchanged(destination, existing),
+            PlannedAction::Discard {
+                duplicate: Some(duplicate),
+            } => unchanged(&duplicate.path, &duplicate.hash),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for Plan {
+    fn default() -> Self {
+        Plan {
+            version: VERSION,
+            imports: Vec::new(),
+        }
+    }
+}
+
+impl Plan {
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let plan: Plan = serde_json::from_reader(std::io::BufReader::new(file))?;
+        if plan.version != VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported plan version {} (expected {})",
+                    plan.version, VERSION
+                ),
+            ));
+        }
+
+        Ok(plan)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        std::io::Write::write_all(&mut file, b"\n")?;
+        std::io::Write::flush(&mut file)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::Config, hash_cache::HashCache, quarantine::Quarantine, template::TemplateContext,
+    };
+
+    #[test]
+    fn test_planned_file_format() {
+        let file = PlannedFile {
+            action: PlannedAction::Import {
+                destination: PathBuf::from("photos/2024/a.jpg"),
+            },
+            source: PathBuf::from("ingestion/a.jpg"),
+            size: 5,
+            modified: 1,
+            hash: None,
+            video: None,
+        };
+
+        let json = serde_json::to_string(&file).unwrap();
+        assert_eq!(
+            json,
+            r#"{"action":"import","destination":"photos/2024/a.jpg","source":"ingestion/a.jpg","size":5,"modified":1}"#
+        );
+        assert_eq!(serde_json::from_str::<PlannedFile>(&json).unwrap(), file);
+
+        let discard: PlannedFile = serde_json::from_str(
+            r#"{"action":"discard","source":"ingestion/a.jpg","size":5,"modified":1}"#,
+        )
+        .unwrap();
+        assert_eq!(discard.action, PlannedAction::Discard { duplicate: None });
+
+        let config = Config::default();
+        let cache = HashCache::disabled();
+        let quarantine =
+            Quarantine::new(&config, TemplateContext::new(&config.quarantine.template));
+        assert!(file
+            .validate(&ConflictManager::new(&config, &cache, &quarantine))
+            .is_err());
+    }
+}
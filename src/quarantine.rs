@@ -0,0 +1,165 @@
+//! The quarantine directory, into which duplicate images are moved instead of being deleted so
+//! that they can be recovered if they were misidentified, along with a ledger recording when each
+//! file was quarantined so that old files can be purged.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+
+use crate::{
+    config::Config,
+    image,
+    store::{format_path, parse_path, Store},
+    template::TemplateContext,
+};
+
+const LEDGER_FILE: &str = "quarantine";
+
+pub struct Quarantine {
+    directory: PathBuf,
+    template: TemplateContext,
+    ledger: Store,
+}
+
+impl Quarantine {
+    pub fn new(config: &Config, template: TemplateContext) -> Self {
+        Quarantine {
+            directory: config.target.join(&config.quarantine.directory),
+            template,
+            ledger: Store::new(&config.target, LEDGER_FILE),
+        }
+    }
+
+    /// The directory into which files are quarantined.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Determines where the given file should be quarantined, rendering the quarantine template
+    /// with the current date and time (rather than the date the image was taken).
+    pub fn path_for(&self, source: &Path) -> PathBuf {
+        let mut metadata = image::Metadata::new(source);
+        metadata.date_time = Some(image::format_timestamp(now().as_secs() as i64));
+
+        let mut path = self.directory.join(self.template.render(&metadata));
+        if let Some(ext) = source.extension() {
+            path = path.with_extension(ext);
+        }
+
+        path
+    }
+
+    /// Records that the file at `path` has just been quarantined.
+    pub fn record(&self, path: &Path) -> std::io::Result<()> {
+        let Ok(relative) = path.strip_prefix(&self.directory) else {
+            return Ok(());
+        };
+
+        self.ledger
+            .append(&[&now().as_secs().to_string(), &format_path(relative)])
+    }
+
+    /// Removes every quarantined file which was quarantined more than `older_than` ago, along with
+    /// any directories left empty, returning the files which were (or in audit mode, would be) removed.
+    pub fn purge(&self, older_than: Duration, audit: bool) -> std::io::Result<Vec<PathBuf>> {
+        let cutoff = now().saturating_sub(older_than).as_secs();
+        let mut purged = Vec::new();
+
+        let mut purge = |records: Vec<Vec<String>>| -> std::io::Result<Vec<Vec<String>>> {
+            let mut retained = Vec::new();
+            for record in records {
+                let Ok(timestamp) = record[0].parse::<u64>() else {
+                    continue;
+                };
+
+                let path = self.directory.join(parse_path(&record[1]));
+                if timestamp > cutoff {
+                    retained.push(record);
+                    continue;
+                }
+
+                if !path.exists() {
+                    continue;
+                }
+
+                info!("rm '{}'", path.display());
+                if audit {
+                    retained.push(record);
+                } else {
+                    std::fs::remove_file(&path)?;
+                    self.remove_empty_parents(&path);
+                }
+
+                purged.push(path);
+            }
+
+            Ok(retained)
+        };
+
+        // The ledger stays locked while files are purged, so that files quarantined by another
+        // run in the meantime are still recorded afterwards.
+        if audit {
+            purge(self.ledger.load(2)?)?;
+        } else {
+            self.ledger.update(2, purge)?;
+        }
+
+        Ok(purged)
+    }
+
+    fn remove_empty_parents(&self, path: &Path) {
+        for dir in path.ancestors().skip(1) {
+            if dir == self.directory || !dir.starts_with(&self.directory) {
+                break;
+            }
+
+            // Removing a directory which still has files in it fails, which is exactly when we want to stop.
+            if std::fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::STATE_DIR;
+
+    #[test]
+    fn test_purge() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let config = Config {
+            target: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let quarantine = Quarantine::new(&config, TemplateContext::new("{name}"));
+        std::fs::create_dir_all(quarantine.directory().join("old")).unwrap();
+        std::fs::write(quarantine.directory().join("old/a.jpg"), "old").unwrap();
+        std::fs::write(quarantine.directory().join("b.jpg"), "new").unwrap();
+
+        std::fs::create_dir_all(dir.join(STATE_DIR)).unwrap();
+        std::fs::write(dir.join(STATE_DIR).join(LEDGER_FILE), "0\told/a.jpg\n").unwrap();
+        quarantine
+            .record(&quarantine.directory().join("b.jpg"))
+            .unwrap();
+
+        let purged = quarantine
+            .purge(Duration::from_secs(30 * 86_400), false)
+            .unwrap();
+        assert_eq!(purged, vec![quarantine.directory().join("old/a.jpg")]);
+        assert!(!quarantine.directory().join("old").exists());
+        assert!(quarantine.directory().join("b.jpg").exists());
+    }
+}
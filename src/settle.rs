@@ -0,0 +1,84 @@
+//! Recognises files which are still being written into the source directory (by a sync client,
+//! an SMB share or a card copy), so that they aren't imported before they are complete.
+
+use std::{path::Path, time::Duration};
+
+use crate::image_data;
+
+/// Whether a file's name marks it as an upload which is still in progress, such as a partial
+/// download (`.part`), an Office lock file (`~$`) or a Synology Drive or sync client temporary file.
+pub fn is_temporary_upload<P: AsRef<Path>>(path: P) -> bool {
+    let Some(name) = path.as_ref().file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+
+    let lowercase = name.to_lowercase();
+    lowercase.ends_with(".part")
+        || lowercase.ends_with(".synotemp")
+        || name.starts_with("~$")
+        || name.starts_with(".sync-")
+}
+
+/// Describes why a file isn't ready to be imported yet: because it was modified within the last
+/// `settle_time`, or (if `verify` is set) because its JPEG or TIFF structure is incomplete.
+pub fn check<P: AsRef<Path>>(path: P, settle_time: Duration, verify: bool) -> Option<String> {
+    let path = path.as_ref();
+
+    if !settle_time.is_zero() {
+        let age = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| modified.elapsed());
+        match age {
+            // A modification time in the future (from a device with a skewed clock) says nothing
+            // about whether the file is still being written, so it mustn't hold the file back forever.
+            Ok(Ok(age)) if age < settle_time => {
+                return Some(format!("modified {}s ago", age.as_secs()));
+            }
+            Ok(_) => {}
+            Err(e) => return Some(e.to_string()),
+        }
+    }
+
+    if verify {
+        match image_data::is_complete(path) {
+            Ok(true) => {}
+            Ok(false) => return Some("incomplete".to_string()),
+            Err(e) => return Some(e.to_string()),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_temporary_upload() {
+        assert!(is_temporary_upload("ingestion/IMG_0001.JPG.part"));
+        assert!(is_temporary_upload("ingestion/IMG_0001.JPG.SYNOTEMP"));
+        assert!(is_temporary_upload("ingestion/~$IMG_0001.JPG"));
+        assert!(is_temporary_upload("ingestion/.sync-IMG_0001.JPG"));
+        assert!(!is_temporary_upload("ingestion/IMG_0001.JPG"));
+        assert!(!is_temporary_upload("ingestion/party.jpg"));
+    }
+
+    #[test]
+    fn test_check_settle_time() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("IMG_0001.JPG");
+        let file = std::fs::File::create(&path).unwrap();
+
+        assert!(check(&path, Duration::from_secs(60), false).is_some());
+        assert!(check(&path, Duration::ZERO, false).is_none());
+
+        file.set_modified(std::time::SystemTime::now() - Duration::from_secs(120))
+            .unwrap();
+        assert!(check(&path, Duration::from_secs(60), false).is_none());
+
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(86_400))
+            .unwrap();
+        assert!(check(&path, Duration::from_secs(60), false).is_none());
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+
+/// Finds the sidecar files which belong to the provided image, matching both on
+/// the image's stem (`DSC0001.xmp`) and on its full name (`DSC0001.ARW.xmp`).
+pub fn find_sidecars<P: AsRef<Path>>(path: P, extensions: &[String]) -> Vec<PathBuf> {
+    let path = path.as_ref();
+    let (Some(parent), Some(stem), Some(name)) =
+        (path.parent(), path.file_stem(), path.file_name())
+    else {
+        return Vec::new();
+    };
+
+    let mut sidecars = Vec::new();
+    for prefix in [stem, name] {
+        for extension in extensions {
+            // Sidecars are usually written with either a lowercase or uppercase extension,
+            // so we check both (once) without needing to list the whole directory.
+            let mut variants = vec![extension.to_lowercase(), extension.to_uppercase()];
+            variants.dedup();
+
+            if let Some(sidecar) = variants
+                .into_iter()
+                .map(|ext| parent.join(format!("{}.{}", prefix.to_string_lossy(), ext)))
+                .find(|candidate| candidate.is_file())
+            {
+                sidecars.push(sidecar);
+            }
+        }
+    }
+
+    sidecars
+}
+
+/// Determines whether the provided file is a sidecar (and should therefore never be imported on its own).
+pub fn is_sidecar<P: AsRef<Path>>(path: P, extensions: &[String]) -> bool {
+    path.as_ref()
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .is_some_and(|ext| extensions.iter().any(|e| e.eq_ignore_ascii_case(&ext)))
+}
@@ -0,0 +1,289 @@
+//! The tab-separated record files which imgsort keeps in the target library's state directory.
+//!
+//! Every store escapes its fields the same way, appends records durably, rewrites itself
+//! atomically and holds a lock while doing so, which keeps concurrent imports (like a watch and a
+//! manual run) from tearing each other's records.
+
+use std::{
+    fs::File,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+/// The directory (within the target library) in which imgsort stores its state.
+pub const STATE_DIR: &str = ".imgsort";
+
+pub struct Store {
+    path: PathBuf,
+}
+
+impl Store {
+    /// The store called `name` within the state directory of the library at `root`.
+    pub fn new<P: AsRef<Path>>(root: P, name: &str) -> Self {
+        Store {
+            path: root.as_ref().join(STATE_DIR).join(name),
+        }
+    }
+
+    /// Reads every record with exactly `fields` fields, skipping any lines which are malformed.
+    ///
+    /// Records are returned in the order they were written, so later records should take
+    /// precedence over earlier ones.
+    pub fn load(&self, fields: usize) -> std::io::Result<Vec<Vec<String>>> {
+        // Reading without a lock is only risky while another process is writing, so a library
+        // which can't be written to (like one being audited) can still be read.
+        let _lock = self.lock(false).ok();
+        self.read(fields)
+    }
+
+    /// Appends a record, persisting it immediately.
+    pub fn append(&self, record: &[&str]) -> std::io::Result<()> {
+        std::fs::create_dir_all(self.path.parent().unwrap())?;
+        let _lock = self.lock(true)?;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(format_record(record).as_bytes())?;
+        file.sync_data()
+    }
+
+    /// Rewrites the store with the records returned by `update`, which is given every record with
+    /// exactly `fields` fields (see [Store::load]), replacing the old file atomically.
+    ///
+    /// The store stays locked from the moment it is re-read until it has been replaced, so records
+    /// which other processes append in the meantime are never lost.
+    pub fn update<F>(&self, fields: usize, update: F) -> std::io::Result<()>
+    where
+        F: FnOnce(Vec<Vec<String>>) -> std::io::Result<Vec<Vec<String>>>,
+    {
+        std::fs::create_dir_all(self.path.parent().unwrap())?;
+        let _lock = self.lock(true)?;
+
+        let records = update(self.read(fields)?)?;
+
+        let temp = self.path.with_extension("tmp");
+        let mut file = std::io::BufWriter::new(File::create(&temp)?);
+        for record in records {
+            let record: Vec<&str> = record.iter().map(String::as_str).collect();
+            file.write_all(format_record(&record).as_bytes())?;
+        }
+
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(temp, &self.path)
+    }
+
+    fn read(&self, fields: usize) -> std::io::Result<Vec<Vec<String>>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut records = Vec::new();
+        for line in std::io::BufReader::new(file).lines() {
+            // The last field may contain unescaped tabs in records written by older versions.
+            let record: Vec<String> = line?.splitn(fields, '\t').map(unescape).collect();
+            if record.len() == fields {
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Locks the store until the returned file is dropped. The lock lives in a separate file so that
+    /// it survives the store being replaced by [Store::update].
+    fn lock(&self, exclusive: bool) -> std::io::Result<File> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))?;
+
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+
+        Ok(file)
+    }
+}
+
+/// Formats a path as a field, escaping backslashes and any bytes which aren't valid UTF-8 (as
+/// `\xHH`) so that [parse_path] recovers exactly the same path.
+pub fn format_path<P: AsRef<Path>>(path: P) -> String {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_ref().as_os_str().as_bytes()
+    };
+
+    #[cfg(not(unix))]
+    let lossy = path.as_ref().to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = lossy.as_bytes();
+
+    let mut formatted = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        formatted.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            formatted.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+
+    formatted
+}
+
+/// Parses a path which was formatted by [format_path]. Unknown escapes are kept as they are,
+/// since older versions stored paths as they were displayed.
+pub fn parse_path(field: &str) -> PathBuf {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+
+        match rest {
+            [b'\\', tail @ ..] => {
+                bytes.push(b'\\');
+                rest = tail;
+            }
+            [b'x', high, low, tail @ ..] => {
+                match std::str::from_utf8(&[*high, *low])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(byte) => {
+                        bytes.push(byte);
+                        rest = tail;
+                    }
+                    None => bytes.push(b'\\'),
+                }
+            }
+            _ => bytes.push(b'\\'),
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        PathBuf::from(std::ffi::OsString::from_vec(bytes))
+    }
+
+    #[cfg(not(unix))]
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn format_record(record: &[&str]) -> String {
+    let mut line = record
+        .iter()
+        .map(|field| escape(field))
+        .collect::<Vec<_>>()
+        .join("\t");
+    line.push('\n');
+    line
+}
+
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        // Unknown escapes are kept as they are, since older versions didn't escape backslashes.
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_records_roundtrip() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = Store::new(temp.path(), "test");
+        assert!(store.load(2).unwrap().is_empty());
+
+        store.append(&["a", "2024/photo\twith tab.jpg"]).unwrap();
+        store.append(&["b", "line\nbreak\\back"]).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(temp.path().join(STATE_DIR).join("test"))
+            .unwrap()
+            .write_all(b"malformed\n")
+            .unwrap();
+
+        let records = store.load(2).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                vec!["a".to_string(), "2024/photo\twith tab.jpg".to_string()],
+                vec!["b".to_string(), "line\nbreak\\back".to_string()],
+            ]
+        );
+
+        store
+            .update(2, |records| {
+                assert_eq!(records.len(), 2);
+                Ok(records.into_iter().skip(1).collect())
+            })
+            .unwrap();
+        assert_eq!(store.load(2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_paths_roundtrip() {
+        for path in ["2024/a.jpg", "C:\\photos\\x41.jpg", "odd\\\\name"] {
+            assert_eq!(parse_path(&format_path(path)), PathBuf::from(path));
+        }
+
+        assert_eq!(parse_path("older\\x4.jpg"), PathBuf::from("older\\x4.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths_roundtrip() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"2024/caf\xe9.jpg"));
+        assert_eq!(format_path(path), "2024/caf\\xe9.jpg");
+        assert_eq!(parse_path(&format_path(path)), path);
+    }
+}
@@ -76,7 +76,7 @@ mod test {
     }
 
     impl<'a> DataSource for TestDataSource<'a> {
-        fn get(&self, key: &str) -> Option<Value> {
+        fn get(&self, key: &str) -> Option<Value<'_>> {
             self.data.get(key).map(|value| Value::Borrowed(value))
         }
     }
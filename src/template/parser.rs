@@ -48,7 +48,7 @@ impl<'a> Iterator for Parser<'a> {
                     return Some(Ok(Segment::Interpolation(field)));
                 }
 
-                return Some(Ok(Segment::Transform(field, transforms)));
+                Some(Ok(Segment::Transform(field, transforms)))
             },
             Some(_) => {
                 let end = self.template[self.pos..].find('{').map(|i| i + self.pos).unwrap_or_else(|| self.template.len());
@@ -0,0 +1,302 @@
+//! Removal of files which are no longer needed, either by deleting them outright or by moving
+//! them into a trash (the freedesktop.org trash or a Synology `#recycle` folder) from which they
+//! can be restored with the usual tools.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::RemovalMethod;
+
+/// Removes the file at `path` using the given method, returning the location it was moved to
+/// if it was not deleted outright.
+pub fn remove(path: &Path, method: RemovalMethod) -> std::io::Result<Option<PathBuf>> {
+    match method {
+        RemovalMethod::Delete => std::fs::remove_file(path).map(|_| None),
+        #[cfg(unix)]
+        RemovalMethod::Trash => freedesktop::trash(path).map(Some),
+        #[cfg(unix)]
+        RemovalMethod::Recycle => synology::recycle(path).map(Some),
+        #[cfg(not(unix))]
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "moving files to the trash is only supported on Unix platforms",
+        )),
+    }
+}
+
+/// Finds a variant of `name` for which `in_use` returns false, appending ` (n)` to its stem if needed.
+#[cfg(unix)]
+fn available_name<F: Fn(&str) -> bool>(name: &str, in_use: F) -> String {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while in_use(&candidate) {
+        candidate = format!("{} ({}){}", stem, n, extension);
+        n += 1;
+    }
+
+    candidate
+}
+
+/// Resolves the directory containing `path` to an absolute path, without following `path` itself
+/// so that a symlink is removed rather than the file it points to.
+#[cfg(unix)]
+fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{}' does not name a file", path.display()),
+        )
+    })?;
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    Ok(std::fs::canonicalize(parent)?.join(name))
+}
+
+/// The root of the filesystem (mount point) on which `path` lives.
+#[cfg(unix)]
+fn mount_point(path: &Path) -> std::io::Result<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = absolute(path)?;
+    let device = std::fs::symlink_metadata(&path)?.dev();
+
+    let mut root = path.as_path();
+    while let Some(parent) = root.parent() {
+        if std::fs::metadata(parent)?.dev() != device {
+            break;
+        }
+
+        root = parent;
+    }
+
+    Ok(root.to_path_buf())
+}
+
+#[cfg(unix)]
+mod freedesktop {
+    use std::{
+        io::Write,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        path::{Path, PathBuf},
+    };
+
+    /// Moves a file into the trash for its filesystem, following the freedesktop.org trash
+    /// specification: the home trash if it lives on the same filesystem, otherwise a trash
+    /// directory at the top of the file's mount.
+    pub fn trash(path: &Path) -> std::io::Result<PathBuf> {
+        let path = super::absolute(path)?;
+        let device = std::fs::symlink_metadata(&path)?.dev();
+
+        let home_trash = home_trash();
+        let (trash, topdir) = match home_trash {
+            Some(trash) if device_of_nearest(&trash)? == device => (trash, None),
+            _ => {
+                let topdir = super::mount_point(&path)?;
+                (topdir_trash(&topdir)?, Some(topdir))
+            }
+        };
+
+        let files = trash.join("files");
+        let info = trash.join("info");
+        std::fs::create_dir_all(&files)?;
+        std::fs::create_dir_all(&info)?;
+        std::fs::set_permissions(&trash, std::fs::Permissions::from_mode(0o700))?;
+
+        // The .trashinfo file is created exclusively first, since it is what reserves the name within the trash.
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let (name, mut trashinfo) = loop {
+            let name = super::available_name(&name, |n| {
+                files.join(n).exists() || info.join(format!("{}.trashinfo", n)).exists()
+            });
+
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(info.join(format!("{}.trashinfo", name)))
+            {
+                Ok(file) => break (name, file),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+
+        // Trashes at the top of a mount record paths relative to it, so that they survive the mount moving.
+        let original = match topdir.as_deref().and_then(|t| path.strip_prefix(t).ok()) {
+            Some(relative) => relative.to_path_buf(),
+            None => path.clone(),
+        };
+
+        writeln!(
+            trashinfo,
+            "[Trash Info]\nPath={}\nDeletionDate={}",
+            encode_path(&original),
+            deletion_date()
+        )?;
+        trashinfo.sync_all()?;
+
+        let destination = files.join(&name);
+        if let Err(e) = std::fs::rename(&path, &destination) {
+            let _ = std::fs::remove_file(info.join(format!("{}.trashinfo", name)));
+            return Err(e);
+        }
+
+        Ok(destination)
+    }
+
+    fn home_trash() -> Option<PathBuf> {
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })?;
+
+        Some(data_home.join("Trash"))
+    }
+
+    /// The device of the nearest existing ancestor of `path`, since the home trash may not exist yet.
+    fn device_of_nearest(path: &Path) -> std::io::Result<u64> {
+        for ancestor in path.ancestors() {
+            if let Ok(metadata) = std::fs::metadata(ancestor) {
+                return Ok(metadata.dev());
+            }
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "unable to find the filesystem of the home trash",
+        ))
+    }
+
+    const STICKY_BIT: u32 = 0o1000;
+
+    /// Picks `$topdir/.Trash/$uid` if the administrator has created a valid (sticky, non-symlink)
+    /// `.Trash` directory, falling back to `$topdir/.Trash-$uid` otherwise.
+    fn topdir_trash(topdir: &Path) -> std::io::Result<PathBuf> {
+        let uid = unsafe { libc::getuid() };
+
+        let shared = topdir.join(".Trash");
+        if let Ok(metadata) = std::fs::symlink_metadata(&shared) {
+            if metadata.is_dir() && metadata.mode() & STICKY_BIT != 0 {
+                return Ok(shared.join(uid.to_string()));
+            }
+        }
+
+        Ok(topdir.join(format!(".Trash-{}", uid)))
+    }
+
+    /// Percent-encodes a path as required by the `Path` key of a `.trashinfo` file.
+    fn encode_path(path: &Path) -> String {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut encoded = String::new();
+        for &byte in path.as_os_str().as_bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                    encoded.push(byte as char)
+                }
+                _ => encoded.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+
+        encoded
+    }
+
+    /// The current local time, formatted as `YYYY-MM-DDThh:mm:ss`.
+    fn deletion_date() -> String {
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe { libc::localtime_r(&now, &mut tm) };
+
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        )
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_encode_path() {
+            assert_eq!(
+                encode_path(Path::new("/photos/2024/Zoë 1.jpg")),
+                "/photos/2024/Zo%C3%AB%201.jpg"
+            );
+        }
+
+        #[test]
+        fn test_trash_symlink() {
+            let temp = tempfile::tempdir().unwrap();
+            let dir = temp.path();
+            std::env::set_var("XDG_DATA_HOME", dir.join("data"));
+            std::fs::write(dir.join("photo.jpg"), "image").unwrap();
+            std::os::unix::fs::symlink(dir.join("photo.jpg"), dir.join("link.jpg")).unwrap();
+
+            let trashed = trash(&dir.join("link.jpg")).unwrap();
+            assert!(std::fs::symlink_metadata(&trashed).unwrap().is_symlink());
+            assert!(std::fs::symlink_metadata(dir.join("link.jpg")).is_err());
+            assert_eq!(std::fs::read(dir.join("photo.jpg")).unwrap(), b"image");
+        }
+    }
+}
+
+#[cfg(unix)]
+mod synology {
+    use std::path::{Path, PathBuf};
+
+    /// Moves a file into the `#recycle` folder of the Synology shared folder it lives in, keeping
+    /// its path within the share so that it can be restored from DSM's recycle bin.
+    pub fn recycle(path: &Path) -> std::io::Result<PathBuf> {
+        let path = super::absolute(path)?;
+        let share = share_root(&path)?;
+        let relative = path.strip_prefix(&share).unwrap_or(&path);
+
+        let target = share.join("#recycle").join(relative);
+        let directory = target.parent().unwrap();
+        std::fs::create_dir_all(directory)?;
+
+        let name = super::available_name(
+            &target.file_name().unwrap_or_default().to_string_lossy(),
+            |n| directory.join(n).exists(),
+        );
+
+        let destination = directory.join(name);
+        std::fs::rename(&path, &destination)?;
+        Ok(destination)
+    }
+
+    /// Shared folders live directly within a volume (`/volume1/photo`), so the share is the
+    /// ancestor which sits below a `volume*` directory, falling back to the mount point.
+    fn share_root(path: &Path) -> std::io::Result<PathBuf> {
+        for ancestor in path.ancestors() {
+            let is_volume = ancestor
+                .parent()
+                .and_then(|p| p.file_name())
+                .map(|n| n.to_string_lossy().starts_with("volume"))
+                .unwrap_or_default();
+
+            if is_volume && ancestor.parent().and_then(|p| p.parent()) == Some(Path::new("/")) {
+                return Ok(ancestor.to_path_buf());
+            }
+        }
+
+        super::mount_point(path)
+    }
+}
@@ -0,0 +1,256 @@
+//! Watches the source directory (and every directory created within it) using inotify, so that
+//! new images can be imported as soon as they have finished being written.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use crate::hash_cache::file_info;
+
+/// How often the watcher checks for changes and for files which have settled.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The size of the buffer into which events are read, which holds a few hundred events at a time.
+const EVENT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A file which has changed recently, along with its size and modification time when it was last seen.
+struct Pending {
+    info: Option<(u64, u64)>,
+    changed: Instant,
+}
+
+pub struct Watcher {
+    inotify: Inotify,
+    directories: HashMap<WatchDescriptor, PathBuf>,
+    pending: HashMap<PathBuf, Pending>,
+    settle: Duration,
+}
+
+impl Watcher {
+    /// Starts watching `root` and every directory beneath it, where files are considered to be
+    /// fully written once they have stopped changing for `settle`.
+    pub fn new<P: AsRef<Path>>(root: P, settle: Duration) -> std::io::Result<Self> {
+        let mut watcher = Watcher {
+            inotify: Inotify::init()?,
+            directories: HashMap::new(),
+            pending: HashMap::new(),
+            settle,
+        };
+
+        watcher.watch_tree(root.as_ref(), false)?;
+        Ok(watcher)
+    }
+
+    /// Reads every event which has arrived since the last call, returning true if the kernel's
+    /// queue overflowed (in which case some changes have been missed and a full rescan is needed).
+    pub fn poll(&mut self) -> std::io::Result<bool> {
+        let mut buffer = vec![0; EVENT_BUFFER_SIZE];
+        let mut overflowed = false;
+
+        loop {
+            let events = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(overflowed),
+                Err(e) => return Err(e),
+            };
+
+            let mut directories = Vec::new();
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    overflowed = true;
+                    continue;
+                }
+
+                if event.mask.contains(EventMask::IGNORED) {
+                    self.directories.remove(&event.wd);
+                    continue;
+                }
+
+                let (Some(directory), Some(name)) = (self.directories.get(&event.wd), event.name)
+                else {
+                    continue;
+                };
+
+                let path = directory.join(name);
+                if event.mask.contains(EventMask::ISDIR) {
+                    directories.push(path);
+                } else {
+                    self.touch(path);
+                }
+            }
+
+            // Files may have been written into a new directory before we started watching it,
+            // so everything already within it is treated as having just changed.
+            for directory in directories {
+                self.watch_tree(&directory, true)?;
+            }
+        }
+    }
+
+    /// Removes and returns the files which have settled: they haven't changed for the settle time,
+    /// nobody has them open for writing, and neither does anybody have any other file which changed
+    /// in the same directory (so that images and their companions are imported together).
+    pub fn ready(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        self.pending.retain(|path, pending| {
+            let info = file_info(path).ok();
+            if info != pending.info {
+                pending.info = info;
+                pending.changed = now;
+            }
+
+            pending.info.is_some()
+        });
+
+        // Finding the files which are open for writing means reading every process's descriptors,
+        // so it is only done once something has stopped changing for long enough to be imported.
+        if !self
+            .pending
+            .values()
+            .any(|pending| now.duration_since(pending.changed) >= self.settle)
+        {
+            return Vec::new();
+        }
+
+        let writing = open_for_writing();
+        let unsettled: HashSet<&Path> = self
+            .pending
+            .iter()
+            .filter(|(path, pending)| {
+                now.duration_since(pending.changed) < self.settle || writing.contains(*path)
+            })
+            .filter_map(|(path, _)| path.parent())
+            .collect();
+
+        let mut ready: Vec<PathBuf> = self
+            .pending
+            .keys()
+            .filter(|path| {
+                !path
+                    .parent()
+                    .is_some_and(|parent| unsettled.contains(parent))
+            })
+            .cloned()
+            .collect();
+        ready.sort();
+
+        for path in ready.iter() {
+            self.pending.remove(path);
+        }
+
+        ready
+    }
+
+    /// Whether a file has changed recently and is still waiting to settle.
+    pub fn is_pending(&self, path: &Path) -> bool {
+        self.pending.contains_key(path)
+    }
+
+    /// Watches `root` and every directory beneath it, optionally marking the files within them as changed.
+    fn watch_tree(&mut self, root: &Path, touch: bool) -> std::io::Result<()> {
+        let mask = WatchMask::CREATE
+            | WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_TO
+            | WatchMask::ATTRIB;
+
+        for entry in walkdir::WalkDir::new(root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+        {
+            if entry.file_type().is_dir() {
+                let wd = self.inotify.watches().add(entry.path(), mask)?;
+                self.directories.insert(wd, entry.into_path());
+            } else if touch && entry.file_type().is_file() {
+                self.touch(entry.into_path());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn touch(&mut self, path: PathBuf) {
+        let pending = self.pending.entry(path).or_insert(Pending {
+            info: None,
+            changed: Instant::now(),
+        });
+        pending.changed = Instant::now();
+    }
+}
+
+/// The files which any process currently has open for writing, as far as we're able to tell
+/// from `/proc` (processes belonging to other users are only visible when running as root).
+pub fn open_for_writing() -> HashSet<PathBuf> {
+    let mut files = HashSet::new();
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return files;
+    };
+
+    for process in processes.filter_map(|entry| entry.ok()) {
+        if !process
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+
+        let Ok(descriptors) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+
+        for descriptor in descriptors.filter_map(|entry| entry.ok()) {
+            let info = process.path().join("fdinfo").join(descriptor.file_name());
+            if is_writable(&info) {
+                if let Ok(target) = std::fs::read_link(descriptor.path()) {
+                    files.insert(target);
+                }
+            }
+        }
+    }
+
+    files
+}
+
+/// Whether the descriptor described by a `/proc/<pid>/fdinfo/<fd>` file was opened for writing.
+fn is_writable(info: &Path) -> bool {
+    let Ok(info) = std::fs::read_to_string(info) else {
+        return false;
+    };
+
+    info.lines()
+        .find_map(|line| line.strip_prefix("flags:"))
+        .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+        .is_some_and(|flags| flags & (libc::O_WRONLY | libc::O_RDWR) as u32 != 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_new_directories_are_watched() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let mut watcher = Watcher::new(dir, Duration::ZERO).unwrap();
+        std::fs::create_dir_all(dir.join("card/DCIM")).unwrap();
+        std::fs::write(dir.join("card/DCIM/a.jpg"), "a").unwrap();
+        let mut writing = std::fs::File::create(dir.join("card/b.jpg")).unwrap();
+
+        assert!(!watcher.poll().unwrap());
+        assert!(open_for_writing().contains(&dir.join("card/b.jpg")));
+        assert_eq!(watcher.ready(), vec![dir.join("card/DCIM/a.jpg")]);
+        assert!(watcher.is_pending(&dir.join("card/b.jpg")));
+
+        std::io::Write::write_all(&mut writing, b"b").unwrap();
+        drop(writing);
+        watcher.poll().unwrap();
+        assert_eq!(watcher.ready(), vec![dir.join("card/b.jpg")]);
+    }
+}